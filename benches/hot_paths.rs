@@ -0,0 +1,106 @@
+//! Benchmarks of the search hot paths on fixed inputs. Run with `cargo bench`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use fairdice::cache::{load_or_compute_inputs, CombineInputs};
+use fairdice::{count_permutation_occurences, is_word_permutation_fair_up_to, subset_fairness, subset_word, CombineOptions, Word, FDTS};
+use itertools::Itertools;
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use common::TempDir;
+
+/// Inputs for combining FDTS of `sizes`, with the subproblems computed in a scratch cache dir
+fn inputs(sizes: &[usize], up_to: usize) -> CombineInputs {
+    let dir = TempDir::new("bench");
+    load_or_compute_inputs(sizes, &subset_fairness(up_to), &dir, &CombineOptions::default())
+        .unwrap()
+        .expect("not stopped")
+}
+
+fn bench_words(c: &mut Criterion) {
+    let f666 = inputs(&[6, 6, 6], 3)
+        .combine(&subset_fairness(3), &CombineOptions::default())
+        .unwrap();
+    let f4666 = inputs(&[4, 6, 6, 6], 3)
+        .combine(&subset_fairness(3), &CombineOptions::default())
+        .unwrap();
+    let w666 = &f666.dice[0].word;
+    let w4666 = &f4666.dice[0].word;
+
+    c.bench_function("count_permutation_occurences [6,6,6]", |b| {
+        b.iter(|| count_permutation_occurences(black_box([2u8, 0, 1]), black_box(w666)))
+    });
+    c.bench_function("count_permutation_occurences [4,6,6,6]", |b| {
+        b.iter(|| count_permutation_occurences(black_box([3u8, 0, 2, 1]), black_box(w4666)))
+    });
+    c.bench_function("is_word_permutation_fair_up_to [6,6,6] fair 3", |b| {
+        b.iter(|| is_word_permutation_fair_up_to(black_box(w666), [0, 1, 2], 3))
+    });
+    c.bench_function("is_word_permutation_fair_up_to [4,6,6,6] fair 3", |b| {
+        b.iter(|| is_word_permutation_fair_up_to(black_box(w4666), [0, 1, 2, 3], 3))
+    });
+}
+
+fn bench_interleave(c: &mut Criterion) {
+    for (sizes, up_to) in [(vec![6, 6, 6], 3), (vec![4, 6, 6, 6], 3)] {
+        let inp = inputs(&sizes, up_to);
+        let d1 = inp.first.0.mapped_as(&inp.first.1);
+        let d2 = inp.second.0.mapped_as(&inp.second.1);
+        let checking = inp.checking.iter().map(|(c, p)| c.mapped_as(p)).collect_vec();
+        let common = d1.map.iter().cloned().filter(|i| d2.map.contains(i)).collect_vec();
+        let target = FDTS::new_empty(&sizes);
+
+        // A fixed sample of compatible word pairs
+        let words2 = d2.iterate_words().collect_vec();
+        let mut pairs = vec![];
+        for w1 in d1.iterate_words() {
+            let key = subset_word(&w1, &common);
+            for w2 in words2.iter().filter(|w2| subset_word(w2, &common) == key) {
+                pairs.push((w1.clone(), w2.clone()));
+            }
+            if pairs.len() >= 64 {
+                break;
+            }
+        }
+
+        for lex in [true, false] {
+            c.bench_function(&format!("interleave_words {:?} lex={}", sizes, lex), |b| {
+                b.iter(|| {
+                    for (w1, w2) in &pairs {
+                        black_box(target.interleave_words(w1, w2, &checking, &common, lex));
+                    }
+                })
+            });
+        }
+
+        let prefixes: Vec<Word> = pairs
+            .iter()
+            .flat_map(|(w1, w2)| target.interleave_words(w1, w2, &[], &common, true).into_iter().take(4))
+            .flat_map(|w| (0..=w.len()).map(move |i| Word::from_slice(&w[..i])))
+            .collect();
+        c.bench_function(&format!("subset_word_in_prefixes {:?}", sizes), |b| {
+            b.iter(|| {
+                prefixes
+                    .iter()
+                    .filter(|w| checking.iter().all(|c| c.subset_word_in_prefixes(w)))
+                    .count()
+            })
+        });
+    }
+}
+
+fn bench_combine(c: &mut Criterion) {
+    let mut group = c.benchmark_group("new_combined");
+    group.sample_size(10);
+    for (sizes, up_to) in [(vec![6, 6, 6], 3), (vec![4, 6, 6, 6], 3)] {
+        let inp = inputs(&sizes, up_to);
+        group.bench_function(format!("{:?} fair {}", sizes, up_to), |b| {
+            b.iter(|| inp.combine(&subset_fairness(up_to), &CombineOptions::default()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_words, bench_interleave, bench_combine);
+criterion_main!(benches);
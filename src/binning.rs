@@ -0,0 +1,166 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use log::debug;
+use rustc_hash::FxHashMap as HashMap;
+
+use crate::{stable_hash, subset_word, MappedFDTS, Word};
+
+/// Upper bound on the spill files open at the same time (well below the common `ulimit -n` of 1024),
+/// more partitions are written in several passes over the words
+const MAX_OPEN_SPILL_FILES: usize = 128;
+
+/// Words grouped by their restriction to the common dice
+pub(crate) type Bins = HashMap<Word, Vec<Word>>;
+
+/// Group `words` into bins by their subword on `bin_indices`
+pub(crate) fn bin_words(words: impl Iterator<Item = Word>, bin_indices: &[usize]) -> Bins {
+    let mut bins = Bins::default();
+    for w in words {
+        bins.entry(subset_word(&w, bin_indices)).or_default().push(w);
+    }
+    bins
+}
+
+/// Approximate memory (in bytes) needed to hold both inputs binned in memory
+pub(crate) fn estimated_bins_memory(d1: &MappedFDTS, d2: &MappedFDTS) -> usize {
+    let word_bytes = |total: usize| {
+        let heap = if total > Word::new().inline_size() { total } else { 0 };
+        // The word itself plus (an upper bound on) its share of the bin key
+        2 * (std::mem::size_of::<Word>() + heap)
+    };
+    d1.fdts.dice.len() * word_bytes(d1.fdts.total) + d2.fdts.dice.len() * word_bytes(d2.fdts.total)
+}
+
+/// Partition of a bin key, stable across runs and platforms
+fn key_partition(key: &[u8], partitions: usize) -> usize {
+    (stable_hash(key) % partitions as u64) as usize
+}
+
+/// Words of both inputs spilled to disk, partitioned by the hash of their bin key.
+///
+/// Every bin lands in exactly one partition, so the partitions can be binned and
+/// combined one at a time. The spill directory is removed on drop.
+pub(crate) struct SpilledBins {
+    dir: PathBuf,
+    partitions: usize,
+    word_lens: [usize; 2],
+    bin_indices: Vec<usize>,
+}
+
+impl SpilledBins {
+    pub(crate) fn create(d1: &MappedFDTS, d2: &MappedFDTS, bin_indices: &[usize], partitions: usize, spill_dir: &Path) -> io::Result<Self> {
+        static SPILL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = spill_dir.join(format!(
+            "fairdice-spill-{}-{}",
+            std::process::id(),
+            SPILL_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        assert!(d1.fdts.total > 0 && d2.fdts.total > 0);
+        fs::create_dir_all(&dir)?;
+        let s = Self {
+            dir,
+            partitions,
+            word_lens: [d1.fdts.total, d2.fdts.total],
+            bin_indices: bin_indices.into(),
+        };
+        debug!(" .. spilling bins into {} partitions in {:?}", partitions, &s.dir);
+        s.spill(0, || d1.iterate_words())?;
+        s.spill(1, || d2.iterate_words())?;
+        Ok(s)
+    }
+
+    fn path(&self, side: usize, partition: usize) -> PathBuf {
+        self.dir.join(format!("part{}_{}.bin", partition, side + 1))
+    }
+
+    /// Write the words into their partitions, at most `MAX_OPEN_SPILL_FILES` partitions per pass over `words()`
+    fn spill<I: Iterator<Item = Word>>(&self, side: usize, words: impl Fn() -> I) -> io::Result<()> {
+        for first in (0..self.partitions).step_by(MAX_OPEN_SPILL_FILES) {
+            let end = (first + MAX_OPEN_SPILL_FILES).min(self.partitions);
+            let mut writers = (first..end)
+                .map(|p| Ok(BufWriter::new(File::create(self.path(side, p))?)))
+                .collect::<io::Result<Vec<_>>>()?;
+            for w in words() {
+                debug_assert_eq!(w.len(), self.word_lens[side]);
+                let p = key_partition(&subset_word(&w, &self.bin_indices), self.partitions);
+                if (first..end).contains(&p) {
+                    writers[p - first].write_all(&w)?;
+                }
+            }
+            for mut w in writers {
+                w.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_bins(&self, side: usize, partition: usize) -> io::Result<Bins> {
+        let mut r = BufReader::new(File::open(self.path(side, partition))?);
+        let mut buf = vec![0u8; self.word_lens[side]];
+        let mut bins = Bins::default();
+        loop {
+            match r.read_exact(&mut buf) {
+                Ok(()) => {
+                    let w = Word::from_slice(&buf);
+                    bins.entry(subset_word(&w, &self.bin_indices)).or_default().push(w);
+                }
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(bins)
+    }
+
+    pub(crate) fn partitions(&self) -> usize {
+        self.partitions
+    }
+
+    /// Load and bin both sides of one partition, deleting its spill files
+    pub(crate) fn load(&self, partition: usize) -> io::Result<(Bins, Bins)> {
+        let bins1 = self.read_bins(0, partition)?;
+        let bins2 = self.read_bins(1, partition)?;
+        for side in 0..2 {
+            fs::remove_file(self.path(side, partition))?;
+        }
+        Ok((bins1, bins2))
+    }
+}
+
+impl Drop for SpilledBins {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::binning::{bin_words, SpilledBins, MAX_OPEN_SPILL_FILES};
+    use crate::temp_dir::TempDir;
+    use crate::FDTS;
+
+    #[test]
+    fn test_spill_in_passes() {
+        let f = FDTS::new_brute_force(&[2, 3], &crate::subset_fairness(1), false);
+        let (d1, d2) = (f.mapped_as(&[0, 1, -1]), f.mapped_as(&[0, -1, 1]));
+        let dir = TempDir::new("test-spill");
+        let partitions = 2 * MAX_OPEN_SPILL_FILES + 1;
+        let spilled = SpilledBins::create(&d1, &d2, &[0], partitions, &dir).unwrap();
+        let (mut words1, mut words2) = (vec![], vec![]);
+        for p in 0..spilled.partitions() {
+            let (b1, b2) = spilled.load(p).unwrap();
+            words1.extend(b1.into_values().flatten());
+            words2.extend(b2.into_values().flatten());
+        }
+        words1.sort();
+        words2.sort();
+        let mut all1 = bin_words(d1.iterate_words(), &[0]).into_values().flatten().collect::<Vec<_>>();
+        all1.sort();
+        assert_eq!(words1, all1);
+        assert_eq!(words2.len(), f.dice.len());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{Criterion, DiceTuple, Word, FDTS};
+
+/// Rearrange `word` into the lexicographically next permutation (of the multiset of its letters),
+/// returning false (and leaving `word` unchanged) if it is already the last one.
+fn next_permutation(word: &mut [u8]) -> bool {
+    let i = match (1..word.len()).rev().find(|&i| word[i - 1] < word[i]) {
+        Some(i) => i - 1,
+        None => return false,
+    };
+    let j = (i + 1..word.len()).rev().find(|&j| word[j] > word[i]).unwrap();
+    word.swap(i, j);
+    word[i + 1..].reverse();
+    true
+}
+
+impl FDTS {
+    /// Find all dice tuples fair by `criterion` by checking every word with the given letter counts.
+    /// Exponentially slow, intended as a reference for small sizes.
+    ///
+    /// With `canonical_only`, keeps only the canonical representative among relabelings of
+    /// equally-sized dice (see `canonical_word`), as combine does.
+    pub fn new_brute_force(sizes: &[usize], criterion: &Criterion, canonical_only: bool) -> Self {
+        let mut f = FDTS::new_empty(sizes);
+        f.criterion = criterion.clone();
+        let mut word: Word = sizes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &s)| std::iter::repeat_n(i as u8, s))
+            .collect();
+        loop {
+            if (!canonical_only || f.canonical_word(&word) == word) && f.criterion.is_fair(&word, f.n()) {
+                f.insert_dice_tuple(DiceTuple::from_word(&f, &word));
+            }
+            if !next_permutation(&mut word) {
+                break;
+            }
+        }
+        f
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use itertools::Itertools;
+
+    use crate::brute_force::next_permutation;
+    use crate::cache::load_or_compute;
+    use crate::temp_dir::TempDir;
+    use crate::{subset_fairness, CombineOptions, FDTS};
+
+    /// Non-descending size tuples of `n` dice with sizes in `1..=max_size`
+    fn size_tuples(n: usize, max_size: usize) -> Vec<Vec<usize>> {
+        (1..=max_size).combinations_with_replacement(n).collect()
+    }
+
+    #[test]
+    fn test_next_permutation() {
+        let mut w = [0, 0, 1, 1];
+        let mut all = vec![w];
+        while next_permutation(&mut w) {
+            all.push(w);
+        }
+        assert_eq!(all.len(), 6);
+        assert!(all.windows(2).all(|p| p[0] < p[1]));
+        assert_eq!(w, [1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_brute_force() {
+        assert_eq!(FDTS::new_brute_force(&[6, 6], &subset_fairness(2), true).dice.len(), 29);
+        assert_eq!(FDTS::new_brute_force(&[2, 2], &subset_fairness(2), false).dice.len(), 2);
+        assert_eq!(FDTS::new_brute_force(&[2, 2], &subset_fairness(2), true).dice.len(), 1);
+        assert_eq!(FDTS::new_brute_force(&[1, 2, 3], &subset_fairness(1), false).dice.len(), 60);
+    }
+
+    #[test]
+    fn test_combined_vs_brute_force() {
+        let single = FDTS::new_single;
+        for sizes in size_tuples(2, 6) {
+            for up_to in 1..=2 {
+                let (d1, d2) = (single(sizes[0]), single(sizes[1]));
+                let f = FDTS::new_combined(d1.mapped_as(&[0, -1]), d2.mapped_as(&[-1, 0]), &[], &subset_fairness(up_to));
+                assert_eq!(
+                    f.dice,
+                    FDTS::new_brute_force(&sizes, &subset_fairness(up_to), true).dice,
+                    "{:?} up to {}",
+                    sizes,
+                    up_to
+                );
+            }
+        }
+        for sizes in size_tuples(3, 4) {
+            for up_to in 2..=3 {
+                let sub = |i: usize, j: usize| FDTS::new_brute_force(&[sizes[i], sizes[j]], &subset_fairness(2), true);
+                let (d01, d02, d12) = (sub(0, 1), sub(0, 2), sub(1, 2));
+                let f = FDTS::new_combined(
+                    d02.mapped_as(&[0, -1, 1]),
+                    d01.mapped_as(&[0, 1, -1]),
+                    &[d12.mapped_as(&[-1, 0, 1])],
+                    &subset_fairness(up_to),
+                );
+                assert_eq!(
+                    f.dice,
+                    FDTS::new_brute_force(&sizes, &subset_fairness(up_to), true).dice,
+                    "{:?} up to {}",
+                    sizes,
+                    up_to
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_pipeline_vs_brute_force() {
+        let dir = TempDir::new("test-brute");
+        let mut cases = size_tuples(2, 5);
+        cases.extend(size_tuples(3, 4));
+        cases.extend([vec![1, 1, 2, 2], vec![2, 2, 2, 2], vec![1, 2, 2, 3], vec![2, 2, 2, 4]]);
+        for sizes in cases {
+            for up_to in 1..=sizes.len() {
+                let f = load_or_compute(&sizes, &subset_fairness(up_to), &dir, &CombineOptions::default()).unwrap();
+                assert_eq!(
+                    f.dice,
+                    FDTS::new_brute_force(&sizes, &subset_fairness(up_to), true).dice,
+                    "{:?} up to {}",
+                    sizes,
+                    up_to
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,677 @@
+use std::{
+    error::Error,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+use log::{info, warn};
+use rustc_hash::FxHashMap as HashMap;
+
+use crate::{CombineOptions, CombineStats, Criterion, DiceTuple, Shard, StopReason, FDTS};
+
+/// Path of the cache file for FDTS of given sizes and fairness criterion (by its tag) within `dir`.
+/// A sharded run writes a partial result next to the full one.
+pub fn cache_path(dir: &Path, sizes: &[usize], criterion: &Criterion, shard: Option<Shard>) -> PathBuf {
+    entry_path(dir, sizes, criterion, shard, "json.zstd")
+}
+
+/// Path of the `CombineStats` (JSON) written next to the cache file when it is computed
+pub fn stats_path(dir: &Path, sizes: &[usize], criterion: &Criterion, shard: Option<Shard>) -> PathBuf {
+    entry_path(dir, sizes, criterion, shard, "stats.json")
+}
+
+/// Path of the fair dice tuples found by local search (see `FDTS::new_local_search`)
+pub fn search_path(dir: &Path, sizes: &[usize], criterion: &Criterion) -> PathBuf {
+    entry_path(dir, sizes, criterion, None, "search.json.zstd")
+}
+
+fn entry_path(dir: &Path, sizes: &[usize], criterion: &Criterion, shard: Option<Shard>, extension: &str) -> PathBuf {
+    let shard_suffix = shard.map_or(String::new(), |s| format!(".shard{}of{}", s.index, s.count));
+    dir.join(format!(
+        "fdts_{}_{}{}.{}",
+        sizes.iter().format("_"),
+        criterion.tag(),
+        shard_suffix,
+        extension
+    ))
+}
+
+/// Read a (zstd-compressed JSON) FDTS file
+pub fn read_fdts(path: &Path) -> Result<FDTS, Box<dyn Error>> {
+    let r = File::open(path)?;
+    let mut r = zstd::Decoder::new(r)?;
+    Ok(FDTS::from_json(&mut r)?)
+}
+
+/// Write a (zstd-compressed JSON) FDTS file
+pub fn write_fdts(f: &FDTS, path: &Path) -> Result<(), Box<dyn Error>> {
+    let w = File::create(path)?;
+    let mut w = zstd::Encoder::new(w, 9)?.auto_finish();
+    f.write_json(&mut w)?;
+    Ok(())
+}
+
+/// Load FDTS of given sizes and fairness from the cache in `dir`, computing it
+/// (and recursively all the required smaller FDTS) if not present.
+///
+/// The statistics of every combine are written to `stats_path` next to its cache file.
+/// When the computation is stopped early (see `CombineOptions::cancel` and `CombineOptions::deadline`),
+/// the result is not `complete` and is returned without being cached, only its statistics are written.
+/// Once a subproblem was stopped, the remaining ones and the combine itself are skipped (the result is empty).
+///
+/// With `CombineOptions::limit`, all the subproblems are computed with the same limit. Results cut short
+/// by it are not cached either: which tuples they contain depends on the timing of the threads.
+pub fn load_or_compute(sizes: &[usize], criterion: &Criterion, dir: &Path, opts: &CombineOptions) -> Result<FDTS, Box<dyn Error>> {
+    assert!(!sizes.is_empty());
+    if sizes.len() == 1 {
+        let mut f = FDTS::new_single(sizes[0]);
+        f.criterion = criterion.clone();
+        assert!(f.criterion.is_fair(&f.dice[0].word, 1));
+        return Ok(f);
+    }
+    let ps = cache_path(dir, sizes, criterion, opts.shard);
+    if ps.exists() {
+        let f = read_fdts(&ps)?;
+        assert_eq!(criterion, &f.criterion);
+        assert_eq!(sizes, f.sizes);
+        assert_eq!(opts.shard, f.shard);
+        info!(
+            "# Read FDTS {} ({}, {} dice tuples) from {:?}",
+            f.sizes_string(),
+            f.criterion,
+            f.dice.len(),
+            &ps
+        );
+        return Ok(f);
+    }
+    // Only the top-level combine is sharded, the subproblems are always computed in full
+    let sub_opts = CombineOptions {
+        shard: None,
+        count_only: false,
+        ..opts.clone()
+    };
+    let inputs = match load_or_compute_inputs(sizes, criterion, dir, &sub_opts)? {
+        Some(inputs) => inputs,
+        None => return Ok(stopped_early(sizes, criterion, opts)),
+    };
+    let (f, stats) = inputs.combine_with_stats(criterion, opts)?;
+    serde_json::to_writer_pretty(File::create(stats_path(dir, sizes, criterion, opts.shard))?, &stats)?;
+    if !f.complete && opts.limit.is_some() && opts.stop_reason().is_none() {
+        info!(
+            "# FDTS {} ({}) is limited to its first {} dice tuples, not caching it",
+            f.sizes_string(),
+            f.criterion,
+            f.dice.len()
+        );
+        return Ok(f);
+    }
+    if !f.complete {
+        warn!(
+            "# FDTS {} ({}) was stopped early, not caching the incomplete result ({} dice tuples)",
+            f.sizes_string(),
+            f.criterion,
+            f.dice.len()
+        );
+        return Ok(f);
+    }
+    write_fdts(&f, &ps)?;
+    info!(
+        "# Saved FDTS [{}] ({}, {} dice tuples{}) to {:?}",
+        f.sizes_string(),
+        f.criterion,
+        f.dice.len(),
+        opts.shard.map_or(String::new(), |s| format!(", shard {}", s)),
+        ps
+    );
+    Ok(f)
+}
+
+/// The empty, incomplete result of a computation stopped while computing its subproblems
+fn stopped_early(sizes: &[usize], criterion: &Criterion, opts: &CombineOptions) -> FDTS {
+    warn!(
+        "# FDTS [{}] ({}) was stopped while computing its subproblems",
+        sizes.iter().format(","),
+        criterion
+    );
+    let mut f = FDTS::new_empty(sizes);
+    f.criterion = criterion.clone();
+    f.shard = opts.shard;
+    f.complete = false;
+    f
+}
+
+/// Find one fair dice tuple of the given sizes, or show that there is none, without enumerating all of them.
+///
+/// The subproblems are computed only up to their first `L` tuples (see `CombineOptions::limit`),
+/// with `L = 1, 2, 4, ...` until the top-level combine finds a tuple or all the subproblems are complete.
+/// Returns an FDTS with one tuple (not `complete` unless it is the only one), or a `complete`
+/// empty one (which is cached as usual). A cached full result is returned as is.
+pub fn find_one(sizes: &[usize], criterion: &Criterion, dir: &Path, opts: &CombineOptions) -> Result<FDTS, Box<dyn Error>> {
+    let ps = cache_path(dir, sizes, criterion, opts.shard);
+    if sizes.len() == 1 || ps.exists() {
+        return load_or_compute(sizes, criterion, dir, opts);
+    }
+    for limit in (0..usize::BITS).map(|i| 1usize << i) {
+        let top_opts = CombineOptions {
+            limit: Some(1),
+            ..opts.clone()
+        };
+        info!(
+            "# Looking for a fair FDTS [{}] ({}) from the first {} subproblem tuples",
+            sizes.iter().format(","),
+            criterion,
+            limit
+        );
+        let sub_opts = CombineOptions {
+            shard: None,
+            limit: Some(limit),
+            ..opts.clone()
+        };
+        let inputs = match load_or_compute_inputs(sizes, criterion, dir, &sub_opts)? {
+            Some(inputs) => inputs,
+            None => return Ok(stopped_early(sizes, criterion, opts)),
+        };
+        let (f, stats) = inputs.combine_with_stats(criterion, &top_opts)?;
+        if f.complete {
+            serde_json::to_writer_pretty(File::create(stats_path(dir, sizes, criterion, opts.shard))?, &stats)?;
+            write_fdts(&f, &ps)?;
+            info!(
+                "# Saved FDTS {} ({}, {} dice tuples) to {:?}",
+                f.sizes_string(),
+                f.criterion,
+                f.dice.len(),
+                ps
+            );
+            return Ok(f);
+        }
+        // Otherwise the inputs were cut short by the limit, retry with more of them
+        if !f.dice.is_empty()
+            || matches!(stats.stopped, Some(StopReason::Cancelled | StopReason::TimeBudget))
+            || opts.stop_reason().is_some()
+        {
+            return Ok(f);
+        }
+    }
+    unreachable!("the subproblems are complete at the latest when the limit exceeds their size")
+}
+
+/// Count the fair dice tuples of the given sizes without storing them (see `CombineOptions::count_only`),
+/// the count is in `CombineStats::results`. The subproblems are loaded or computed (and cached) as usual,
+/// a cached full result is counted directly.
+pub fn count(sizes: &[usize], criterion: &Criterion, dir: &Path, opts: &CombineOptions) -> Result<CombineStats, Box<dyn Error>> {
+    let ps = cache_path(dir, sizes, criterion, opts.shard);
+    if sizes.len() == 1 || ps.exists() {
+        let f = load_or_compute(sizes, criterion, dir, opts)?;
+        return Ok(CombineStats {
+            sizes: f.sizes.clone(),
+            criterion: f.criterion.tag(),
+            results: f.dice.len(),
+            complete: f.complete,
+            ..Default::default()
+        });
+    }
+    let sub_opts = CombineOptions {
+        shard: None,
+        count_only: false,
+        ..opts.clone()
+    };
+    let top_opts = CombineOptions {
+        count_only: true,
+        ..opts.clone()
+    };
+    let inputs = match load_or_compute_inputs(sizes, criterion, dir, &sub_opts)? {
+        Some(inputs) => inputs,
+        None => {
+            return Ok(CombineStats {
+                sizes: sizes.into(),
+                criterion: criterion.tag(),
+                stopped: opts.stop_reason(),
+                ..Default::default()
+            })
+        }
+    };
+    let (_, stats) = inputs.combine_with_stats(criterion, &top_opts)?;
+    serde_json::to_writer_pretty(File::create(stats_path(dir, sizes, criterion, opts.shard))?, &stats)?;
+    Ok(stats)
+}
+
+/// The smaller FDTS that FDTS of some sizes are combined from, each with the positions
+/// of its dice among the combined dice (`-1` for the missing one), as for `FDTS::mapped_as`
+pub struct CombineInputs {
+    pub first: (FDTS, Vec<isize>),
+    pub second: (FDTS, Vec<isize>),
+    pub checking: Vec<(FDTS, Vec<isize>)>,
+}
+
+impl CombineInputs {
+    /// Combine the inputs, the result is incomplete if any of the inputs is
+    pub fn combine(&self, criterion: &Criterion, opts: &CombineOptions) -> std::io::Result<FDTS> {
+        Ok(self.combine_with_stats(criterion, opts)?.0)
+    }
+
+    pub fn combine_with_stats(&self, criterion: &Criterion, opts: &CombineOptions) -> std::io::Result<(FDTS, CombineStats)> {
+        let (d1, p1) = &self.first;
+        let (d2, p2) = &self.second;
+        let (mut f, mut stats) = FDTS::new_combined_with_stats(
+            d1.mapped_as(p1),
+            d2.mapped_as(p2),
+            self.checking.iter().map(|(c, p)| c.mapped_as(p)).collect_vec().as_slice(),
+            criterion,
+            opts,
+        )?;
+        let inputs_complete = d1.complete && d2.complete && self.checking.iter().all(|(c, _)| c.complete);
+        f.complete &= inputs_complete;
+        stats.complete &= inputs_complete;
+        Ok((f, stats))
+    }
+}
+
+/// Load (or compute) the inputs for combining FDTS of the given sizes (at least 2 dice):
+/// the FDTS omitting the last, the second to last, and each of the other dice.
+/// Returns `None` as soon as an input was stopped early (see `CombineOptions::stop_reason`),
+/// without computing the others, as the combine of them would be incomplete anyway.
+pub fn load_or_compute_inputs(
+    sizes: &[usize],
+    criterion: &Criterion,
+    dir: &Path,
+    opts: &CombineOptions,
+) -> Result<Option<CombineInputs>, Box<dyn Error>> {
+    let n = sizes.len();
+    assert!(n >= 2);
+
+    /// Sizes, positions and criterion of the FDTS omitting the dice at `position`
+    fn sizes_and_mapped_positions(sizes: &[usize], position: usize, criterion: &Criterion) -> (Vec<usize>, Vec<isize>, Criterion) {
+        let mut a_sizes: Vec<usize> = sizes.into();
+        a_sizes.remove(position);
+        let mut a_positions: Vec<isize> = (0..(sizes.len() - 1) as isize).collect();
+        a_positions.insert(position, -1);
+        let dice = (0..sizes.len()).filter(|&i| i != position).collect_vec();
+        (a_sizes, a_positions, criterion.restrict(&dice))
+    }
+
+    info!("# Gathering data for FDTS [{}] ({}) ...", sizes.iter().format(","), criterion);
+    let (a_s, a_p, a_c) = sizes_and_mapped_positions(sizes, n - 2, criterion);
+    let stopped = |f: &FDTS| !f.complete && opts.stop_reason().is_some();
+    let da = load_or_compute(&a_s, &a_c, dir, opts)?;
+    if stopped(&da) {
+        return Ok(None);
+    }
+    let (b_s, b_p, b_c) = sizes_and_mapped_positions(sizes, n - 1, criterion);
+    let db = load_or_compute(&b_s, &b_c, dir, opts)?;
+    if stopped(&db) {
+        return Ok(None);
+    }
+
+    let mut checking = vec![];
+    for i in 0..(n - 2) {
+        let (c_s, c_p, c_c) = sizes_and_mapped_positions(sizes, i, criterion);
+        let dc = load_or_compute(&c_s, &c_c, dir, opts)?;
+        if stopped(&dc) {
+            return Ok(None);
+        }
+        checking.push((dc, c_p));
+    }
+    Ok(Some(CombineInputs {
+        first: (da, a_p),
+        second: (db, b_p),
+        checking,
+    }))
+}
+
+/// Merge partial FDTS files (e.g. all the shards of a sharded run) into the regular cache entry in `dir`,
+/// which `load_or_compute` then reads as usual. Records the input file names in the result.
+pub fn merge_files(paths: &[PathBuf], dir: &Path) -> Result<(FDTS, PathBuf), Box<dyn Error>> {
+    let parts: Vec<FDTS> = paths.iter().map(|p| read_fdts(p)).try_collect()?;
+    for (p, f) in paths.iter().zip(&parts) {
+        info!(
+            "# Read FDTS {} ({}, {} dice tuples{}) from {:?}",
+            f.sizes_string(),
+            f.criterion,
+            f.dice.len(),
+            f.shard.map_or(String::new(), |s| format!(", shard {}", s)),
+            p
+        );
+    }
+    let mut f = FDTS::merge(&parts)?;
+    f.merged_from = paths
+        .iter()
+        .map(|p| p.file_name().unwrap_or(p.as_os_str()).to_string_lossy().into_owned())
+        .collect();
+    let ps = cache_path(dir, &f.sizes, &f.criterion, None);
+    write_fdts(&f, &ps)?;
+    info!(
+        "# Saved merged FDTS {} ({}, {} dice tuples from {} parts) to {:?}",
+        f.sizes_string(),
+        f.criterion,
+        f.dice.len(),
+        parts.len(),
+        ps
+    );
+    Ok((f, ps))
+}
+
+/// Add dice tuples found by local search to the ones found by earlier searches in `search_path`.
+/// Returns all of them (not `complete`) and the path.
+pub fn save_search_results(f: &FDTS, dir: &Path) -> Result<(FDTS, PathBuf), Box<dyn Error>> {
+    let ps = search_path(dir, &f.sizes, &f.criterion);
+    let mut all = if ps.exists() { read_fdts(&ps)?.union(f) } else { f.clone() };
+    all.complete = false;
+    write_fdts(&all, &ps)?;
+    info!(
+        "# Saved {} found FDTS {} ({}, {} dice tuples in total) to {:?}",
+        f.dice.len(),
+        all.sizes_string(),
+        all.criterion,
+        all.dice.len(),
+        ps
+    );
+    Ok((all, ps))
+}
+
+/// Sizes and fairness criterion tag of a full (not sharded) cache file name as written by `cache_path`.
+/// The criterion itself may need the parameters stored in the file (see `FairnessCriterion::params`).
+pub fn parse_cache_file_name(name: &str) -> Option<(Vec<usize>, String)> {
+    let stem = name.strip_prefix("fdts_")?.strip_suffix(".json.zstd")?;
+    let (sizes, tag) = stem.rsplit_once('_')?;
+    if tag.contains('.') {
+        return None;
+    }
+    let sizes: Vec<usize> = sizes.split('_').map(|s| s.parse().ok()).collect::<Option<_>>()?;
+    Some((sizes, tag.into()))
+}
+
+/// A dice tuple of a cached FDTS whose projection is missing from the cached FDTS of the sub-problem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheViolation {
+    pub file: PathBuf,
+    pub sub_file: PathBuf,
+    /// The dice omitted by the projection
+    pub omitted: usize,
+    /// The (canonical) projected word missing from `sub_file`
+    pub projected: String,
+    /// Number of tuples in `file` projecting onto it
+    pub count: usize,
+}
+
+/// Check that the projection of every cached FDTS onto all its (n-1)-subsets of dice is contained,
+/// up to relabeling of equally-sized dice, in the corresponding cached sub-problem (if present).
+/// Returns all the violations found; any indicates a bug or a corrupt cache file.
+pub fn check_cache(dir: &Path) -> Result<Vec<CacheViolation>, Box<dyn Error>> {
+    let mut entries = vec![];
+    for e in std::fs::read_dir(dir)? {
+        let e = e?;
+        if let Some((sizes, tag)) = parse_cache_file_name(&e.file_name().to_string_lossy()) {
+            entries.push((sizes, tag, e.path()));
+        }
+    }
+    entries.sort();
+
+    // Sub-problems are shared by many entries, keep them loaded
+    let mut loaded_subs = HashMap::<PathBuf, FDTS>::default();
+
+    let mut violations = vec![];
+    for (sizes, _, path) in entries {
+        let n = sizes.len();
+        if n < 3 {
+            // Projections onto single dice are trivially fair
+            continue;
+        }
+        let f = read_fdts(&path)?;
+        let criterion = &f.criterion;
+        let mut checked = 0;
+        for omitted in 0..n {
+            let sub_dice = (0..n).filter(|&i| i != omitted).collect_vec();
+            let sub_sizes = sub_dice.iter().map(|&i| sizes[i]).collect_vec();
+            let sub_path = cache_path(dir, &sub_sizes, &criterion.restrict(&sub_dice), None);
+            if !sub_path.exists() {
+                continue;
+            }
+            if !loaded_subs.contains_key(&sub_path) {
+                loaded_subs.insert(sub_path.clone(), read_fdts(&sub_path)?);
+            }
+            let sub = &loaded_subs[&sub_path];
+            let (proj, counts) = f.project_with_counts(&sub_dice);
+            for (d, count) in proj.dice.iter().zip(counts) {
+                let dc = DiceTuple::from_word(sub, &sub.canonical_word(&d.word));
+                if !sub.contains(&dc) {
+                    violations.push(CacheViolation {
+                        file: path.clone(),
+                        sub_file: sub_path.clone(),
+                        omitted,
+                        projected: dc.as_string(),
+                        count,
+                    });
+                }
+            }
+            checked += 1;
+        }
+        info!(
+            "# Checked FDTS {} ({}, {} dice tuples) against {} sub-problems",
+            f.sizes_string(),
+            f.criterion,
+            f.dice.len(),
+            checked
+        );
+    }
+    Ok(violations)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use crate::cache::{
+        cache_path, check_cache, count, find_one, load_or_compute, merge_files, parse_cache_file_name, read_fdts, save_search_results,
+        search_path, stats_path, write_fdts,
+    };
+    use crate::temp_dir::TempDir;
+    use crate::{
+        is_word_permutation_fair_up_to, subset_fairness, CancellationToken, CombineOptions, Criterion, DiceTuple, GoFirstFairness,
+        LocalSearchOptions, Shard, TopPlacesFairness, WeightedFairness, FDTS,
+    };
+
+    #[test]
+    fn test_merge_shards() {
+        let dir = TempDir::new("test-merge");
+        let shards = (0..3)
+            .map(|i| {
+                let opts = CombineOptions {
+                    shard: Some(Shard::new(i, 3)),
+                    ..Default::default()
+                };
+                load_or_compute(&[6, 6, 6], &subset_fairness(3), &dir, &opts).unwrap();
+                cache_path(&dir, &[6, 6, 6], &subset_fairness(3), opts.shard)
+            })
+            .collect::<Vec<_>>();
+        assert!(!cache_path(&dir, &[6, 6, 6], &subset_fairness(3), None).exists());
+        let (merged, path) = merge_files(&shards, &dir).unwrap();
+        assert_eq!(merged.dice.len(), 11);
+        assert_eq!(merged.merged_from.len(), 3);
+        assert_eq!(path, cache_path(&dir, &[6, 6, 6], &subset_fairness(3), None));
+        let cached = load_or_compute(&[6, 6, 6], &subset_fairness(3), &dir, &CombineOptions::default()).unwrap();
+        assert_eq!(cached, merged);
+
+        // The merged words are in the same order as in a single full run
+        let full_dir = dir.join("full");
+        std::fs::create_dir_all(&full_dir).unwrap();
+        let full = load_or_compute(&[6, 6, 6], &subset_fairness(3), &full_dir, &CombineOptions::default()).unwrap();
+        assert_eq!(full.dice, merged.dice);
+    }
+
+    #[test]
+    fn test_check_cache() {
+        assert_eq!(
+            parse_cache_file_name("fdts_4_6_6_fair3.json.zstd"),
+            Some((vec![4, 6, 6], "fair3".into()))
+        );
+        assert_eq!(parse_cache_file_name("fdts_6_6_fair2.shard0of3.json.zstd"), None);
+        assert_eq!(parse_cache_file_name("other.json"), None);
+
+        let dir = TempDir::new("test-check");
+        let f = load_or_compute(&[6, 6, 6], &subset_fairness(3), &dir, &CombineOptions::default()).unwrap();
+        assert_eq!(check_cache(&dir).unwrap(), vec![]);
+
+        // Drop the projection of the first tuple from the sub-problem
+        let sub_path = cache_path(&dir, &[6, 6], &subset_fairness(2), None);
+        let sub = load_or_compute(&[6, 6], &subset_fairness(2), &dir, &CombineOptions::default()).unwrap();
+        let mut removed = FDTS::new_empty(&[6, 6]);
+        removed.criterion = sub.criterion.clone();
+        let projected = sub.canonical_word(&f.project(&[1, 2]).dice[0].word);
+        removed.insert_dice_tuple(DiceTuple::from_word(&sub, &projected));
+        write_fdts(&sub.difference(&removed), &sub_path).unwrap();
+        let violations = check_cache(&dir).unwrap();
+        assert!(!violations.is_empty());
+        assert!(violations
+            .iter()
+            .all(|v| v.sub_file == sub_path && v.projected == removed.dice[0].as_string()));
+    }
+
+    #[test]
+    fn test_go_first() {
+        let dir = TempDir::new("test-go-first");
+        let go_first = Criterion::new(GoFirstFairness);
+        for sizes in &[vec![1, 2, 3], vec![2, 3, 3], vec![2, 3, 4], vec![2, 2, 2], vec![1, 2, 3, 4]] {
+            let f = load_or_compute(sizes, &go_first, &dir, &CombineOptions::default()).unwrap();
+            assert_eq!(f.dice, FDTS::new_brute_force(sizes, &go_first, true).dice, "{:?}", sizes);
+        }
+        let path = cache_path(&dir, &[2, 3, 4], &go_first, None);
+        assert!(path.to_string_lossy().ends_with("fdts_2_3_4_gofirst.json.zstd"));
+        assert_eq!(
+            parse_cache_file_name("fdts_2_3_4_gofirst.json.zstd"),
+            Some((vec![2, 3, 4], go_first.tag()))
+        );
+        assert_eq!(read_fdts(&path).unwrap().criterion, go_first);
+        assert_eq!(check_cache(&dir).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_top_places() {
+        let dir = TempDir::new("test-top-places");
+        // With 3 dice, the first 2 places determine the whole order
+        let top2 = Criterion::new(TopPlacesFairness(2));
+        let f = load_or_compute(&[2, 4, 6], &top2, &dir, &CombineOptions::default()).unwrap();
+        let fair = load_or_compute(&[2, 4, 6], &subset_fairness(3), &dir, &CombineOptions::default()).unwrap();
+        assert_eq!(f.dice.len(), 3);
+        assert_eq!(f.dice, fair.dice);
+        assert!(cache_path(&dir, &[2, 4, 6], &top2, None).exists());
+    }
+
+    #[test]
+    fn test_weighted() {
+        let dir = TempDir::new("test-weighted");
+        // ABC and CBA twice as likely as the others; all the words are kept, not only the canonical ones
+        let weighted = Criterion::new(WeightedFairness::new(3, vec![2, 1, 1, 1, 1, 2]));
+        for sizes in &[vec![2, 2, 4], vec![2, 3, 4], vec![2, 4, 4], vec![4, 4, 6]] {
+            let f = load_or_compute(sizes, &weighted, &dir, &CombineOptions::default()).unwrap();
+            assert_eq!(f.dice, FDTS::new_brute_force(sizes, &weighted, false).dice, "{:?}", sizes);
+        }
+        let f = read_fdts(&cache_path(&dir, &[4, 4, 6], &weighted, None)).unwrap();
+        assert_eq!(f.dice.len(), 8);
+        assert_eq!(f.criterion, weighted);
+        assert_eq!(check_cache(&dir).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_incomplete_not_cached() {
+        let dir = TempDir::new("test-incomplete");
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let opts = CombineOptions {
+            cancel: Some(cancel),
+            ..Default::default()
+        };
+        let f = load_or_compute(&[6, 6, 6], &subset_fairness(3), &dir, &opts).unwrap();
+        assert!(!f.complete);
+        assert!(f.dice.is_empty());
+        // Only the statistics of the first subproblem are written, the others and the top-level combine are skipped
+        let stats = std::fs::read_to_string(stats_path(&dir, &[6, 6], &subset_fairness(2), None)).unwrap();
+        assert!(stats.contains("\"complete\": false"));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // The deadline is for the whole computation, not for every combine
+        let timed_out = CombineOptions {
+            deadline: Some(Instant::now()),
+            ..Default::default()
+        };
+        assert!(!load_or_compute(&[6, 6, 6], &subset_fairness(3), &dir, &timed_out).unwrap().complete);
+        assert!(!find_one(&[6, 6, 6], &subset_fairness(3), &dir, &timed_out).unwrap().complete);
+        assert!(!count(&[6, 6, 6], &subset_fairness(3), &dir, &timed_out).unwrap().complete);
+        assert!(!stats_path(&dir, &[6, 6, 6], &subset_fairness(3), None).exists());
+
+        // The flag survives a round trip through a file
+        let path = dir.join("incomplete.json.zstd");
+        write_fdts(&f, &path).unwrap();
+        assert!(!read_fdts(&path).unwrap().complete);
+    }
+
+    #[test]
+    fn test_find_one() {
+        let dir = TempDir::new("test-find-one");
+        let opts = CombineOptions::default();
+        let f = find_one(&[6, 6, 6], &subset_fairness(3), &dir, &opts).unwrap();
+        assert_eq!(f.dice.len(), 1);
+        assert!(!f.complete);
+        assert!(!cache_path(&dir, &[6, 6, 6], &subset_fairness(3), None).exists());
+        // Only the subproblems that were computed in full are cached, not the ones cut short by the limit
+        for e in std::fs::read_dir(&dir).unwrap() {
+            let path = e.unwrap().path();
+            if path.to_string_lossy().ends_with(".json.zstd") {
+                assert!(read_fdts(&path).unwrap().complete, "{:?}", path);
+            }
+        }
+
+        let full = load_or_compute(&[6, 6, 6], &subset_fairness(3), &dir, &opts).unwrap();
+        assert!(full.contains(&f.dice[0]));
+
+        // With 4 dice, the first tuples of the direct inputs need deeper subproblems to grow (16 exist)
+        let f = find_one(&[4, 6, 6, 6], &subset_fairness(3), &dir, &opts).unwrap();
+        assert_eq!(f.dice.len(), 1);
+        assert!(is_word_permutation_fair_up_to(&f.dice[0].word, [0, 1, 2, 3], 3));
+
+        // Showing there is none gives a complete (cached) result
+        let none = find_one(&[4, 4, 4], &subset_fairness(3), &dir, &opts).unwrap();
+        assert!(none.complete && none.dice.is_empty());
+        assert!(cache_path(&dir, &[4, 4, 4], &subset_fairness(3), None).exists());
+    }
+
+    #[test]
+    fn test_count() {
+        let dir = TempDir::new("test-count");
+        let stats = count(&[6, 6, 6], &subset_fairness(2), &dir, &CombineOptions::default()).unwrap();
+        assert_eq!(stats.results, 2421);
+        assert!(stats.complete);
+        // Only the subproblems are cached
+        assert!(!cache_path(&dir, &[6, 6, 6], &subset_fairness(2), None).exists());
+        assert!(cache_path(&dir, &[6, 6], &subset_fairness(2), None).exists());
+
+        let full = load_or_compute(&[6, 6, 6], &subset_fairness(2), &dir, &CombineOptions::default()).unwrap();
+        assert_eq!(
+            count(&[6, 6, 6], &subset_fairness(2), &dir, &CombineOptions::default())
+                .unwrap()
+                .results,
+            full.dice.len()
+        );
+    }
+
+    #[test]
+    fn test_save_search_results() {
+        let dir = TempDir::new("test-search");
+        let search = |seed| {
+            let opts = LocalSearchOptions {
+                seed,
+                ..Default::default()
+            };
+            FDTS::new_local_search(&[6, 6], 2, &opts)
+        };
+        let (first, path) = save_search_results(&search(1), &dir).unwrap();
+        assert_eq!(path, search_path(&dir, &[6, 6], &subset_fairness(2)));
+        assert_eq!(first.dice.len(), 1);
+        let (all, _) = save_search_results(&search(2), &dir).unwrap();
+        assert!(first.dice.iter().all(|d| all.contains(d)));
+        assert_eq!(read_fdts(&path).unwrap(), all);
+        assert!(!all.complete);
+        // Search results are not taken for cache entries
+        assert_eq!(parse_cache_file_name(&path.file_name().unwrap().to_string_lossy()), None);
+    }
+}
@@ -0,0 +1,156 @@
+use itertools::Itertools;
+use log::info;
+
+use crate::{is_sorted, Criterion, DiceTuple, Word, FDTS};
+
+/// All relabelings of the dice that keep the sizes, as maps from old to new labels
+fn size_preserving_relabelings(sizes: &[usize]) -> Vec<Vec<u8>> {
+    let groups = (0..sizes.len()).group_by(|&i| sizes[i]);
+    groups
+        .into_iter()
+        .map(|(_, g)| {
+            let g = g.map(|i| i as u8).collect_vec();
+            g.iter().cloned().permutations(g.len()).collect_vec()
+        })
+        .multi_cartesian_product()
+        .map(|perms| perms.concat())
+        .collect()
+}
+
+impl FDTS {
+    /// Verify `word` and insert its canonical form, returning whether it was fair and new
+    fn insert_if_fair(&mut self, word: &[u8]) -> bool {
+        if !self.criterion.is_fair(word, self.n()) {
+            return false;
+        }
+        let canonical = self.canonical_word(word);
+        self.insert_dice_tuple(DiceTuple::from_word(self, &canonical))
+    }
+
+    /// Construct fair dice tuples of sizes `a.sizes + b.sizes` by following every word of `a`
+    /// with every word of `b`, reversed or not and with its equally-sized dice relabeled in all ways.
+    /// Such blocks are always fair when the sizes of `b` are those of `a` all multiplied by the same factor
+    /// (e.g. from `a` and `b` both fair [6, 6, 6] dice we get [12, 12, 12] dice); with different factors per dice
+    /// they usually are not (e.g. `ABBA` followed by fair [4, 6] dice), other results are kept when fair.
+    ///
+    /// Every word is verified by `criterion`, the result is not `complete`.
+    pub fn new_concatenated(a: &FDTS, b: &FDTS, criterion: &Criterion) -> Self {
+        assert_eq!(a.n(), b.n());
+        let sizes = a.sizes.iter().zip(&b.sizes).map(|(x, y)| x + y).collect_vec();
+        let mut f = FDTS::new_empty(&sizes);
+        f.criterion = criterion.clone();
+        f.complete = false;
+        let relabelings = size_preserving_relabelings(&b.sizes);
+        for da in &a.dice {
+            for db in &b.dice {
+                for (relabel, reverse) in relabelings.iter().cartesian_product([false, true].iter()) {
+                    let mut second: Word = db.word.iter().map(|&x| relabel[x as usize]).collect();
+                    if *reverse {
+                        second.reverse();
+                    }
+                    let word: Word = da.word.iter().chain(second.iter()).cloned().collect();
+                    f.insert_if_fair(&word);
+                }
+            }
+        }
+        info!(
+            "# Constructed FDTS {} ({}, {} dice tuples) from {} and {} by concatenation",
+            f.sizes_string(),
+            f.criterion,
+            f.dice.len(),
+            a.sizes_string(),
+            b.sizes_string()
+        );
+        f
+    }
+
+    /// Construct fair dice tuples by substituting each letter of every word of `outer` by a block: letter `i`
+    /// by a word of `blocks[i]`, with the dice of the blocks numbered consecutively (so the sizes are the sizes
+    /// of the blocks multiplied by the sizes of their outer dice, and they need to be non-descending).
+    /// All the occurrences of a letter get the same block word; all choices of the block words are tried.
+    /// A single-dice block of size `c` multiplies the size of the dice by `c`, which always keeps the fairness.
+    ///
+    /// Every word is verified by `criterion`, the result is not `complete`.
+    pub fn new_substituted(outer: &FDTS, blocks: &[&FDTS], criterion: &Criterion) -> Self {
+        assert_eq!(outer.n(), blocks.len());
+        let sizes = blocks
+            .iter()
+            .zip(&outer.sizes)
+            .flat_map(|(b, &s)| b.sizes.iter().map(move |&t| s * t))
+            .collect_vec();
+        assert!(is_sorted(&sizes), "sizes of the blocks need to be non-descending");
+        let mut f = FDTS::new_empty(&sizes);
+        f.criterion = criterion.clone();
+        f.complete = false;
+        let block_offsets = blocks
+            .iter()
+            .scan(0, |s, b| {
+                *s += b.n();
+                Some((*s - b.n()) as u8)
+            })
+            .collect_vec();
+        for d in &outer.dice {
+            for choice in blocks.iter().map(|b| b.dice.iter()).multi_cartesian_product() {
+                let word: Word = d
+                    .word
+                    .iter()
+                    .flat_map(|&x| {
+                        let offset = block_offsets[x as usize];
+                        choice[x as usize].word.iter().map(move |&y| y + offset)
+                    })
+                    .collect();
+                f.insert_if_fair(&word);
+            }
+        }
+        info!(
+            "# Constructed FDTS {} ({}, {} dice tuples) from {} by substitution",
+            f.sizes_string(),
+            f.criterion,
+            f.dice.len(),
+            outer.sizes_string()
+        );
+        f
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::constructions::size_preserving_relabelings;
+    use crate::test::fair_d6_d6_d6;
+    use crate::{is_word_permutation_fair_up_to, subset_fairness, FDTS};
+
+    #[test]
+    fn test_relabelings() {
+        assert_eq!(size_preserving_relabelings(&[2, 3]), vec![vec![0, 1]]);
+        assert_eq!(size_preserving_relabelings(&[1, 2, 2]), vec![vec![0, 1, 2], vec![0, 2, 1]]);
+        assert_eq!(size_preserving_relabelings(&[4, 4, 4, 6, 6]).len(), 12);
+    }
+
+    #[test]
+    fn test_concatenated() {
+        let d666 = fair_d6_d6_d6();
+        let f = FDTS::new_concatenated(d666, d666, &subset_fairness(3));
+        assert!(!f.complete);
+        assert_eq!(f.sizes, vec![12, 12, 12]);
+        assert_eq!(f.dice.len(), 726);
+        assert!(f.dice.iter().all(|d| is_word_permutation_fair_up_to(&d.word, [0, 1, 2], 3)));
+        assert!(f.dice.iter().all(|d| f.canonical_word(&d.word) == d.word));
+    }
+
+    #[test]
+    fn test_substituted() {
+        let d2_d2 = FDTS::new_brute_force(&[2, 2], &subset_fairness(2), true);
+        // Multiplying the sizes keeps all the fair dice
+        let f = FDTS::new_substituted(&d2_d2, &[&FDTS::new_single(3), &FDTS::new_single(3)], &subset_fairness(2));
+        assert_eq!(f.sizes, vec![6, 6]);
+        assert_eq!(f.dice[0].as_string(), "AAABBBBBBAAA");
+
+        // The letters of fair [6, 6] dice replaced by A -> AA and B -> BCCB (or CBBC)
+        let d6_d6 = FDTS::new_brute_force(&[6, 6], &subset_fairness(2), true);
+        let f = FDTS::new_substituted(&d6_d6, &[&FDTS::new_single(2), &d2_d2], &subset_fairness(3));
+        assert_eq!(f.sizes, vec![12, 12, 12]);
+        assert_eq!(f.dice.len(), 2);
+        assert!(f.dice.iter().all(|d| is_word_permutation_fair_up_to(&d.word, [0, 1, 2], 3)));
+        assert!(!f.complete);
+    }
+}
@@ -0,0 +1,596 @@
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Display};
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use itertools::Itertools;
+use serde::Deserialize;
+use smallvec::{smallvec, SmallVec};
+
+use crate::{
+    count_permutation_occurences, count_permutations, is_word_go_first_fair, is_word_permutation_fair_up_to, is_word_top_places_fair,
+    stable_hash,
+};
+
+/// A notion of fairness of dice tuples, deciding which words combine accepts.
+///
+/// The search relies on every restriction of a fair tuple to a subset of its dice satisfying
+/// the criterion given by `restrict`: the subproblems are computed with it and the combine
+/// only interleaves (and prunes against) their dice tuples.
+pub trait FairnessCriterion: Debug + Display + Send + Sync {
+    /// Short name used in cache file names (as in `fdts_6_6_6_{tag}.json.zstd`), see `parse_criterion`.
+    /// Different parameters (see `params`) need to give different tags.
+    fn tag(&self) -> String;
+
+    /// Whether `word` over the dice `0..n` is fair
+    fn is_fair(&self, word: &[u8], n: usize) -> bool;
+
+    /// The criterion that all the restrictions of fair dice tuples to the given (ascending) subset of their dice satisfy
+    fn restrict(&self, dice: &[usize]) -> Criterion;
+
+    /// Whether the criterion does not change by relabeling equally-sized dice, so that only the
+    /// canonical words need to be kept (see `FDTS::canonical_word`)
+    fn is_symmetric(&self) -> bool;
+
+    /// The check whether prefixes may still be completed to fair words of dice of `sizes`, prepared once per combine.
+    /// Combine prunes the interleavings by it in addition to the subproblems (default: no pruning).
+    fn prefix_check(&self, _sizes: &[usize]) -> Option<Box<dyn PrefixCheck>> {
+        None
+    }
+
+    /// Check that the criterion applies to dice tuples of `n` dice
+    fn check_dice(&self, _n: usize) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Parameters of the criterion its tag does not determine (as the weights of a `WeightedFairness`),
+    /// stored along with the dice tuples for `parse_criterion` to rebuild the criterion
+    fn params(&self) -> Option<serde_json::Value> {
+        None
+    }
+}
+
+/// Pruning of the prefixes of the words of given dice sizes, see `FairnessCriterion::prefix_check`
+pub trait PrefixCheck: Send + Sync {
+    /// Whether `prefix` may still be completed to a fair word
+    fn can_extend(&self, prefix: &[u8]) -> bool;
+}
+
+impl<F: Fn(&[u8]) -> bool + Send + Sync> PrefixCheck for F {
+    fn can_extend(&self, prefix: &[u8]) -> bool {
+        self(prefix)
+    }
+}
+
+/// A shared fairness criterion, criteria are equal if their tags are
+#[derive(Debug, Clone)]
+pub struct Criterion(Arc<dyn FairnessCriterion>);
+
+impl Criterion {
+    pub fn new(c: impl FairnessCriterion + 'static) -> Self {
+        Criterion(Arc::new(c))
+    }
+
+    /// Whether `prefix` may still be completed to a fair word of dice of `sizes`,
+    /// preparing the `prefix_check` for just this prefix
+    pub fn can_extend(&self, prefix: &[u8], sizes: &[usize]) -> bool {
+        self.prefix_check(sizes).is_none_or(|c| c.can_extend(prefix))
+    }
+}
+
+impl Deref for Criterion {
+    type Target = dyn FairnessCriterion;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl PartialEq for Criterion {
+    fn eq(&self, other: &Self) -> bool {
+        self.tag() == other.tag()
+    }
+}
+
+impl Eq for Criterion {}
+
+impl Display for Criterion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+/// Every `k`-subset of the dice is permutation fair (see `is_word_permutation_fair_up_to`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubsetFairness(pub usize);
+
+impl Display for SubsetFairness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fair up to {}", self.0)
+    }
+}
+
+impl FairnessCriterion for SubsetFairness {
+    fn tag(&self) -> String {
+        format!("fair{}", self.0)
+    }
+
+    fn is_fair(&self, word: &[u8], n: usize) -> bool {
+        let values: Vec<u8> = (0..n as u8).collect();
+        // Every word is fair up to 0
+        self.0 == 0 || is_word_permutation_fair_up_to(word, values, self.0)
+    }
+
+    fn restrict(&self, dice: &[usize]) -> Criterion {
+        subset_fairness(std::cmp::min(self.0, dice.len()))
+    }
+
+    fn check_dice(&self, n: usize) -> Result<(), String> {
+        if self.0 > n {
+            return Err(format!("cannot be fair up to {} with only {} dice", self.0, n));
+        }
+        Ok(())
+    }
+
+    fn is_symmetric(&self) -> bool {
+        true
+    }
+}
+
+/// Every dice is equally likely to roll the highest number ("go-first" dice), see `is_word_go_first_fair`.
+/// This says nothing about the subsets of the dice, so the subproblems are all the words.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GoFirstFairness;
+
+impl Display for GoFirstFairness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "go-first fair")
+    }
+}
+
+impl FairnessCriterion for GoFirstFairness {
+    fn tag(&self) -> String {
+        "gofirst".into()
+    }
+
+    fn is_fair(&self, word: &[u8], n: usize) -> bool {
+        is_word_go_first_fair(word, n)
+    }
+
+    fn restrict(&self, _dice: &[usize]) -> Criterion {
+        subset_fairness(1)
+    }
+
+    fn is_symmetric(&self) -> bool {
+        true
+    }
+
+    /// Every dice needs to go first in `product(sizes) / n` cases.
+    fn prefix_check(&self, sizes: &[usize]) -> Option<Box<dyn PrefixCheck>> {
+        let n = sizes.len();
+        let total: u64 = sizes.iter().map(|&s| s as u64).product();
+        if !total.is_multiple_of(n as u64) {
+            return Some(Box::new(|_: &[u8]| false));
+        }
+        Some(Box::new(GoFirstCheck::new(sizes, vec![total / n as u64; n])))
+    }
+}
+
+/// Whether prefixes may be completed to words where every dice `i` goes first in `targets[i]` cases. Every further
+/// occurrence of a dice adds at least the product of the numbers of the other dice seen so far, and at most their sizes.
+struct GoFirstCheck {
+    sizes: Vec<u64>,
+    targets: Vec<u64>,
+    /// For every dice, the product of the sizes of the other dice
+    others_sizes: Vec<u64>,
+}
+
+impl GoFirstCheck {
+    fn new(sizes: &[usize], targets: Vec<u64>) -> Self {
+        let sizes: Vec<u64> = sizes.iter().map(|&s| s as u64).collect();
+        let others_sizes = (0..sizes.len())
+            .map(|i| (0..sizes.len()).filter(|&j| j != i).map(|j| sizes[j]).product())
+            .collect();
+        GoFirstCheck {
+            sizes,
+            targets,
+            others_sizes,
+        }
+    }
+}
+
+impl PrefixCheck for GoFirstCheck {
+    fn can_extend(&self, prefix: &[u8]) -> bool {
+        let n = self.sizes.len();
+        // As `count_go_first`, without allocating for the usual numbers of dice
+        let mut seen: SmallVec<[u64; 8]> = smallvec![0; n];
+        let mut counts: SmallVec<[u64; 8]> = smallvec![0; n];
+        for &x in prefix {
+            let x = x as usize;
+            counts[x] += (0..n).filter(|&j| j != x).map(|j| seen[j]).product::<u64>();
+            seen[x] += 1;
+        }
+        (0..n).all(|i| {
+            let rest = self.sizes[i] - seen[i];
+            let least = (0..n).filter(|&j| j != i).map(|j| seen[j]).product::<u64>();
+            counts[i] + rest * least <= self.targets[i] && counts[i] + rest * self.others_sizes[i] >= self.targets[i]
+        })
+    }
+}
+
+/// The dice taking the first `k` places (rolling the `k` highest numbers, in order) are equally likely to be any
+/// ordered `k`-tuple of the dice, see `is_word_top_places_fair`. Like go-first fairness (`k = 1`) this says nothing
+/// about the subsets of the dice; for `k >= n - 1` it is full fairness, which `SubsetFairness(n)` finds much faster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TopPlacesFairness(pub usize);
+
+impl Display for TopPlacesFairness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fair in the first {} places", self.0)
+    }
+}
+
+impl FairnessCriterion for TopPlacesFairness {
+    fn tag(&self) -> String {
+        format!("top{}", self.0)
+    }
+
+    fn is_fair(&self, word: &[u8], n: usize) -> bool {
+        is_word_top_places_fair(word, n, std::cmp::min(self.0, n))
+    }
+
+    fn check_dice(&self, n: usize) -> Result<(), String> {
+        if self.0 > n {
+            return Err(format!("cannot be fair in the first {} places with only {} dice", self.0, n));
+        }
+        Ok(())
+    }
+
+    fn restrict(&self, _dice: &[usize]) -> Criterion {
+        subset_fairness(1)
+    }
+
+    fn is_symmetric(&self) -> bool {
+        true
+    }
+
+    /// The number of ordered `k`-tuples needs to divide `product(sizes)`, and the first place is go-first fair.
+    fn prefix_check(&self, sizes: &[usize]) -> Option<Box<dyn PrefixCheck>> {
+        let n = sizes.len();
+        let k = std::cmp::min(self.0, n);
+        if k == 0 {
+            return None;
+        }
+        let total: u64 = sizes.iter().map(|&s| s as u64).product();
+        let tuples: u64 = (n - k + 1..=n).map(|i| i as u64).product();
+        if !total.is_multiple_of(tuples) {
+            return Some(Box::new(|_: &[u8]| false));
+        }
+        GoFirstFairness.prefix_check(sizes)
+    }
+}
+
+/// Every permutation of the dice occurs in proportion to its given weight, e.g. to favor one player.
+/// The weights are in the order of `(0..n).permutations(n)` and reduced by their common divisor,
+/// where a permutation lists the dice from the lowest to the highest number (as the words do).
+///
+/// Relabeling the dice changes the criterion, so all the words are kept (not only the canonical ones);
+/// the restrictions to subsets of the dice are weighted by the marginal weights.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WeightedFairness {
+    n: usize,
+    weights: Vec<u64>,
+}
+
+impl WeightedFairness {
+    pub fn new(n: usize, weights: Vec<u64>) -> Self {
+        Self::try_new(n, weights).unwrap()
+    }
+
+    /// As `new`, failing on weights that are not one for every permutation, all zero, or summing past `u64::MAX`
+    pub fn try_new(n: usize, weights: Vec<u64>) -> Result<Self, String> {
+        if weights.len() != (1..=n).product::<usize>() {
+            return Err(format!("need a weight for every permutation of {} dice", n));
+        }
+        let divisor = weights.iter().fold(0, |a, &b| gcd(a, b));
+        if divisor == 0 {
+            return Err("need a positive weight".into());
+        }
+        if weight_sum(&weights).is_none() {
+            return Err(format!("the weights need to sum to at most {}", u64::MAX));
+        }
+        Ok(WeightedFairness {
+            n,
+            weights: weights.iter().map(|w| w / divisor).collect(),
+        })
+    }
+
+    /// Weights of the permutations given as words (e.g. `"ABC"`) of the dice `0..n`, missing permutations have weight 0
+    pub fn from_named(n: usize, named: &HashMap<String, u64>) -> Result<Self, String> {
+        let perms = (0..n as u8).permutations(n).collect_vec();
+        let mut weights = vec![0; perms.len()];
+        for (name, &w) in named {
+            let p: Vec<u8> = name.bytes().map(|c| c.wrapping_sub(b'A')).collect();
+            let i = perms
+                .iter()
+                .position(|q| *q == p)
+                .ok_or_else(|| format!("{:?} is not a permutation of {} dice", name, n))?;
+            weights[i] = w;
+        }
+        WeightedFairness::try_new(n, weights)
+    }
+
+    /// The target number of occurrences of every permutation in a fair word of dice of `sizes`, if integral
+    fn targets(&self, sizes: &[usize]) -> Option<Vec<u64>> {
+        // In u128, as the products of the weights and the total overflow u64 for large weights
+        let total: u64 = sizes.iter().map(|&s| s as u64).product();
+        let (total, sum) = (total as u128, self.weights.iter().sum::<u64>() as u128);
+        if !self.weights.iter().all(|&w| (w as u128 * total).is_multiple_of(sum)) {
+            return None;
+        }
+        // At most the total, as no weight exceeds the sum
+        Some(self.weights.iter().map(|&w| (w as u128 * total / sum) as u64).collect())
+    }
+
+    /// Identifies the weights in the tag (and so in the cache file names): the `stable_hash` of `n`
+    /// and the reduced weights, each as a little-endian `u64`
+    fn hash(&self) -> u64 {
+        let bytes: Vec<u8> = std::iter::once(self.n as u64)
+            .chain(self.weights.iter().copied())
+            .flat_map(u64::to_le_bytes)
+            .collect();
+        stable_hash(&bytes)
+    }
+}
+
+/// Sum of the weights, if it fits into a `u64` (then so do all the marginal weights of `restrict`)
+fn weight_sum(weights: &[u64]) -> Option<u64> {
+    weights.iter().try_fold(0u64, |a, &w| a.checked_add(w))
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl Display for WeightedFairness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "weighted fair {:016x}", self.hash())
+    }
+}
+
+impl FairnessCriterion for WeightedFairness {
+    fn tag(&self) -> String {
+        format!("weighted{:016x}", self.hash())
+    }
+
+    fn is_fair(&self, word: &[u8], n: usize) -> bool {
+        assert_eq!(n, self.n);
+        let counts = count_permutations(word, n);
+        let sum = self.weights.iter().sum::<u64>() as u128;
+        let total = counts.iter().sum::<u64>() as u128;
+        counts
+            .iter()
+            .zip(&self.weights)
+            .all(|(&c, &w)| c as u128 * sum == w as u128 * total)
+    }
+
+    fn restrict(&self, dice: &[usize]) -> Criterion {
+        let k = dice.len();
+        let sub_perms = (0..k as u8).permutations(k).collect_vec();
+        let mut weights = vec![0; sub_perms.len()];
+        for (p, &w) in (0..self.n).permutations(self.n).zip(&self.weights) {
+            let q: Vec<u8> = p.iter().filter_map(|x| dice.iter().position(|d| d == x)).map(|i| i as u8).collect();
+            weights[sub_perms.iter().position(|s| *s == q).unwrap()] += w;
+        }
+        Criterion::new(WeightedFairness::new(k, weights))
+    }
+
+    fn is_symmetric(&self) -> bool {
+        false
+    }
+
+    fn check_dice(&self, n: usize) -> Result<(), String> {
+        if n != self.n {
+            return Err(format!("weights are for {} dice, not {}", self.n, n));
+        }
+        Ok(())
+    }
+
+    /// Every permutation occurs at most its target number of times, and every dice goes first
+    /// (i.e. ends the permutations) as often as the weights of these permutations demand.
+    fn prefix_check(&self, sizes: &[usize]) -> Option<Box<dyn PrefixCheck>> {
+        let targets = match self.targets(sizes) {
+            Some(t) => t,
+            None => return Some(Box::new(|_: &[u8]| false)),
+        };
+        let mut go_first = vec![0; self.n];
+        for (p, &t) in (0..self.n).permutations(self.n).zip(&targets) {
+            go_first[*p.last().unwrap()] += t;
+        }
+        let go_first = GoFirstCheck::new(sizes, go_first);
+        let perms = (0..self.n as u8).permutations(self.n).collect_vec();
+        // The go-first bounds are much cheaper than counting all the n! permutations, so they go first
+        Some(Box::new(move |prefix: &[u8]| {
+            go_first.can_extend(prefix)
+                && perms
+                    .iter()
+                    .zip(&targets)
+                    .all(|(p, &t)| count_permutation_occurences(p, prefix) <= t)
+        }))
+    }
+
+    fn params(&self) -> Option<serde_json::Value> {
+        Some(serde_json::json!({ "n": self.n, "weights": self.weights }))
+    }
+}
+
+/// The criterion that every `k`-subset of the dice is permutation fair
+pub fn subset_fairness(k: usize) -> Criterion {
+    Criterion::new(SubsetFairness(k))
+}
+
+/// The criterion with the given `tag` and `params` (see `FairnessCriterion::params`), if it is a known one
+pub fn parse_criterion(tag: &str, params: Option<&serde_json::Value>) -> Option<Criterion> {
+    if tag.starts_with("weighted") {
+        #[derive(Deserialize)]
+        struct Params {
+            n: usize,
+            weights: Vec<u64>,
+        }
+        let p = Params::deserialize(params?).ok()?;
+        let c = WeightedFairness::try_new(p.n, p.weights).ok()?;
+        // The tag identifies the parameters, so they need to agree
+        return (c.tag() == tag).then(|| Criterion::new(c));
+    }
+    if tag == GoFirstFairness.tag() {
+        return Some(Criterion::new(GoFirstFairness));
+    }
+    if let Some(k) = tag.strip_prefix("top") {
+        return Some(Criterion::new(TopPlacesFairness(k.parse().ok()?)));
+    }
+    let k = tag.strip_prefix("fair")?.parse().ok()?;
+    Some(subset_fairness(k))
+}
+
+impl FromStr for Criterion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_criterion(s, None).ok_or_else(|| format!("unknown fairness criterion {:?} (use e.g. fair3, top2 or gofirst)", s))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::criterion::{parse_criterion, subset_fairness, Criterion, GoFirstFairness, TopPlacesFairness, WeightedFairness};
+    use crate::{is_word_go_first_fair, is_word_top_places_fair, FDTS};
+
+    #[test]
+    fn test_subset_fairness() {
+        let c = subset_fairness(3);
+        assert_eq!(c.tag(), "fair3");
+        assert_eq!(c.to_string(), "fair up to 3");
+        assert_eq!(parse_criterion("fair3", None), Some(c.clone()));
+        assert_eq!(parse_criterion("fair", None), None);
+        assert_eq!(parse_criterion("unfair3", None), None);
+        assert!(c.is_symmetric());
+        assert_eq!(c.restrict(&[0, 2]), subset_fairness(2));
+        assert_eq!(c.restrict(&[0, 1, 3]), c);
+        assert!(c.check_dice(3).is_ok());
+        assert!(c.check_dice(2).is_err());
+
+        assert!(!c.is_fair(&[0, 1, 2, 2, 1, 0], 3));
+        assert!(c.restrict(&[0, 1]).is_fair(&[0, 1, 1, 0], 2));
+        assert!(subset_fairness(2).is_fair(&[0, 1, 2, 2, 1, 0], 3));
+    }
+
+    #[test]
+    fn test_go_first() {
+        let c: Criterion = "gofirst".parse().unwrap();
+        assert_eq!(c, Criterion::new(GoFirstFairness));
+        assert!("gofirst3".parse::<Criterion>().is_err());
+        assert_eq!(c.restrict(&[0, 1]), subset_fairness(1));
+        assert!(c.is_fair(&[1, 2, 2, 0, 1, 2], 3));
+
+        // 2 * 2 * 2 is not divisible by 3
+        assert!(!c.can_extend(&[], &[2, 2, 2]));
+        // Pruning never cuts off a fair word
+        let sizes = [2, 3, 4];
+        let all = FDTS::new_brute_force(&sizes, &subset_fairness(1), false);
+        let mut fair = 0;
+        for d in &all.dice {
+            let prunes = (0..=d.word.len()).filter(|&i| !c.can_extend(&d.word[..i], &sizes)).count();
+            if is_word_go_first_fair(&d.word, 3) {
+                fair += 1;
+                assert_eq!(prunes, 0);
+            } else {
+                assert!(prunes > 0);
+            }
+        }
+        assert_eq!(fair, 15);
+    }
+
+    #[test]
+    fn test_top_places() {
+        let c: Criterion = "top2".parse().unwrap();
+        assert_eq!(c, Criterion::new(TopPlacesFairness(2)));
+        assert_eq!(c.to_string(), "fair in the first 2 places");
+        assert!("top".parse::<Criterion>().is_err());
+        assert_eq!(c.restrict(&[0, 1, 2]), subset_fairness(1));
+        // Go-first fair, but not in the first 2 places
+        assert!(!c.is_fair(&[1, 2, 2, 0, 1, 2], 3));
+        // 1 * 2 * 2 * 4 is divisible by 4 (as go-first needs), but not by the 4 * 3 ordered pairs
+        assert!(c.can_extend(&[], &[2, 3, 4]));
+        assert!(!c.can_extend(&[], &[1, 2, 2, 4]));
+        assert!(Criterion::new(GoFirstFairness).can_extend(&[], &[1, 2, 2, 4]));
+
+        // Pruning never cuts off a fair word
+        let sizes = [2, 4, 6];
+        let all = FDTS::new_brute_force(&sizes, &subset_fairness(1), false);
+        let mut fair = 0;
+        for d in &all.dice {
+            if is_word_top_places_fair(&d.word, 3, 2) {
+                fair += 1;
+                assert!((0..=d.word.len()).all(|i| c.can_extend(&d.word[..i], &sizes)));
+            }
+        }
+        assert_eq!(fair, 3);
+    }
+
+    #[test]
+    fn test_weighted() {
+        // ABC and CBA twice as likely as the others
+        let named: HashMap<String, u64> = ["ABC", "ACB", "BAC", "BCA", "CAB", "CBA"]
+            .iter()
+            .zip(&[2, 1, 1, 1, 1, 2])
+            .map(|(p, &w)| (p.to_string(), w))
+            .collect();
+        let w = WeightedFairness::from_named(3, &named).unwrap();
+        assert_eq!(w, WeightedFairness::new(3, vec![4, 2, 2, 2, 2, 4]));
+        assert!(WeightedFairness::from_named(3, &[("ABD".to_string(), 1)].iter().cloned().collect()).is_err());
+        assert!(WeightedFairness::from_named(2, &named).is_err());
+        let c = Criterion::new(w);
+        // The tag stays the same across builds, as the cache file names depend on it
+        assert_eq!(c.tag(), "weightedc2388bbd67cb0f66");
+        assert_eq!(parse_criterion(&c.tag(), None), None);
+        assert_eq!(parse_criterion(&c.tag(), c.params().as_ref()), Some(c.clone()));
+        // Parameters that do not give the tag
+        let other = Criterion::new(WeightedFairness::new(3, vec![1, 1, 1, 1, 1, 2])).params();
+        assert_eq!(parse_criterion(&c.tag(), other.as_ref()), None);
+        assert!(!c.is_symmetric());
+        // Every pair is uniform, A comes before C in 4 of 8 cases
+        assert_eq!(c.restrict(&[0, 2]), Criterion::new(WeightedFairness::new(2, vec![1, 1])));
+        assert_eq!(c.restrict(&[1]), Criterion::new(WeightedFairness::new(1, vec![1])));
+        let skewed = Criterion::new(WeightedFairness::new(3, vec![1, 1, 0, 0, 0, 0]));
+        assert_eq!(skewed.restrict(&[0, 1]), Criterion::new(WeightedFairness::new(2, vec![1, 0])));
+
+        // 2 * 4 * 4 is divisible by the total weight 8, but not 2 * 3 * 3
+        assert!(c.can_extend(&[], &[2, 4, 4]));
+        assert!(!c.can_extend(&[], &[2, 3, 3]));
+        // Pruning never cuts off a fair word
+        let sizes = [2, 4, 4];
+        let all = FDTS::new_brute_force(&sizes, &subset_fairness(1), false);
+        let mut fair = 0;
+        for d in &all.dice {
+            if c.is_fair(&d.word, 3) {
+                fair += 1;
+                assert!((0..=d.word.len()).all(|i| c.can_extend(&d.word[..i], &sizes)));
+            }
+        }
+        assert_eq!(fair, 3);
+
+        // Large weights do not overflow, but their sum needs to fit
+        let large = Criterion::new(WeightedFairness::new(2, vec![u64::MAX / 2, u64::MAX / 2 - 1]));
+        assert!(!large.is_fair(&[0, 1, 1, 0], 2));
+        assert!(!large.can_extend(&[], &[2, 2]));
+        let too_large: HashMap<String, u64> = [("AB".to_string(), u64::MAX), ("BA".to_string(), 1)].iter().cloned().collect();
+        assert!(WeightedFairness::from_named(2, &too_large).is_err());
+    }
+}
@@ -31,8 +31,8 @@ impl DiceTuple {
         assert_eq!(word.len(), f.total);
         let mut numbers: Word = Word::from_elem(0, f.total);
         let mut offsets = f.offsets.clone();
-        for i in 0..f.total {
-            let dn = word[i] as usize;
+        for (i, &d) in word.iter().enumerate() {
+            let dn = d as usize;
             numbers[offsets[dn]] = i as u8;
             offsets[dn] += 1;
         }
@@ -47,7 +47,7 @@ impl DiceTuple {
             .chars()
             .map(|c| {
                 let c = c as usize;
-                assert!(c >= 65 && c < 65 + 26, "invalid word character (only A-Z allowed)");
+                assert!((65..65 + 26).contains(&c), "invalid word character (only A-Z allowed)");
                 (c - 65) as u8
             })
             .collect_vec();
@@ -64,3 +64,32 @@ impl std::fmt::Debug for DiceTuple {
         f.write_str(&self.as_string())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use crate::test::sizes_and_word;
+    use crate::{DiceTuple, FDTS};
+
+    proptest! {
+        #[test]
+        fn prop_conversions((sizes, w) in sizes_and_word(6, 8)) {
+            let f = FDTS::new_empty(&sizes);
+            let d = DiceTuple::from_word(&f, &w);
+            prop_assert_eq!(&d.word, &w);
+            // Every number is used once, increasing on every dice
+            let mut all = d.numbers.to_vec();
+            all.sort_unstable();
+            prop_assert!(all.iter().enumerate().all(|(i, &x)| i == x as usize));
+            for dn in 0..f.n() {
+                let ns = &d.numbers[f.offsets[dn]..f.offsets[dn] + f.sizes[dn]];
+                prop_assert!(ns.windows(2).all(|p| p[0] < p[1]));
+                prop_assert!(ns.iter().all(|&x| w[x as usize] == dn as u8));
+            }
+            prop_assert_eq!(&DiceTuple::from_numbers(&f, &d.numbers), &d);
+            prop_assert_eq!(&DiceTuple::from_string(&f, &d.as_string()), &d);
+            prop_assert_eq!(d.as_string().len(), f.total);
+        }
+    }
+}
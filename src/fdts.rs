@@ -1,8 +1,8 @@
 use std::io::{Read, Write};
 
-use crate::{is_sorted, is_word_permutation_fair_up_to, DiceTuple, MappedFDTS, Word};
+use crate::{is_sorted, parse_criterion, subset_fairness, Criterion, DiceTuple, MappedFDTS, Shard, Word};
 use itertools::Itertools;
-use rustc_hash::FxHashSet as HashSet;
+use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -12,16 +12,46 @@ pub struct FDTS {
     pub offsets: Vec<usize>,
     pub dice: Vec<DiceTuple>,
     pub prefixes: HashSet<Word>,
-    pub fair_up_to: usize,
+    /// The fairness all the dice tuples satisfy
+    pub criterion: Criterion,
+    /// For a partial result of a sharded combine, the shard it covers
+    pub shard: Option<Shard>,
+    /// For a merged FDTS, the inputs it was merged from
+    pub merged_from: Vec<String>,
+    /// False for the partial result of a combine that was stopped early (or built from such results),
+    /// which may be missing dice tuples
+    pub complete: bool,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 struct StoredFDTS {
     pub sizes: Vec<usize>,
-    pub fair_up_to: usize,
+    /// Tag of the criterion, see `parse_criterion`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub criterion: Option<String>,
+    /// Older files only have the `SubsetFairness`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fair_up_to: Option<usize>,
+    /// Parameters of the criterion not determined by its tag, see `FairnessCriterion::params`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<Shard>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merged_from: Vec<String>,
+    #[serde(default = "default_complete", skip_serializing_if = "is_complete")]
+    pub complete: bool,
     pub words: Vec<String>,
 }
 
+fn default_complete() -> bool {
+    true
+}
+
+fn is_complete(complete: &bool) -> bool {
+    *complete
+}
+
 impl FDTS {
     /// Create empty FDTS with given sizes
     pub fn new_empty(sizes: &[usize]) -> Self {
@@ -38,7 +68,10 @@ impl FDTS {
                 .collect(),
             dice: vec![],
             prefixes: HashSet::default(),
-            fair_up_to: 0,
+            criterion: subset_fairness(0),
+            shard: None,
+            merged_from: vec![],
+            complete: true,
         }
     }
 
@@ -46,7 +79,7 @@ impl FDTS {
     pub fn new_single(size: usize) -> Self {
         let mut f = FDTS::new_empty(&[size]);
         f.insert_dice_tuple(DiceTuple::from_word(&f, &vec![0u8; size]));
-        f.fair_up_to = 1;
+        f.criterion = subset_fairness(1);
         f
     }
 
@@ -54,31 +87,232 @@ impl FDTS {
         let s = StoredFDTS {
             sizes: self.sizes.clone(),
             words: self.dice.iter().map(|d| d.as_string()).collect(),
-            fair_up_to: self.fair_up_to,
+            criterion: Some(self.criterion.tag()),
+            fair_up_to: None,
+            params: self.criterion.params(),
+            shard: self.shard,
+            merged_from: self.merged_from.clone(),
+            complete: self.complete,
         };
         serde_json::to_writer_pretty(writer, &s)
     }
 
     pub fn from_json(reader: impl Read) -> serde_json::Result<Self> {
         let s: StoredFDTS = serde_json::from_reader(reader)?;
+        let fair_up_to = s.fair_up_to;
+        let tag = s
+            .criterion
+            .or_else(|| fair_up_to.map(|k| subset_fairness(k).tag()))
+            .ok_or_else(|| serde::de::Error::missing_field("criterion"))?;
         let mut f = FDTS::new_empty(&s.sizes);
-        f.fair_up_to = s.fair_up_to;
-        assert!(f.fair_up_to <= f.n());
-        let values = (0..f.n() as u8).collect_vec();
+        f.criterion = parse_criterion(&tag, s.params.as_ref())
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown fairness criterion {:?} or its parameters", tag)))?;
+        f.shard = s.shard;
+        f.merged_from = s.merged_from;
+        f.complete = s.complete;
         for w in s.words {
             let dt = DiceTuple::from_string(&f, &w);
-            assert!(is_word_permutation_fair_up_to(&dt.word, &values, f.fair_up_to));
-            f.insert_dice_tuple(dt)
+            assert!(f.criterion.is_fair(&dt.word, f.n()));
+            f.insert_dice_tuple(dt);
+        }
+        Ok(f)
+    }
+
+    /// Merge partial results (shards) of the same sizes and fairness into one FDTS, dropping duplicates.
+    ///
+    /// The result is complete if any part is complete or the parts cover all shards of one sharding,
+    /// otherwise this fails, as it does for incomplete parts. The parts are recorded in `merged_from`.
+    /// The words are sorted, so the result does not depend on the order of the parts.
+    pub fn merge(parts: &[FDTS]) -> Result<FDTS, String> {
+        let first = parts.first().ok_or("nothing to merge")?;
+        for p in parts {
+            if p.sizes != first.sizes || p.criterion != first.criterion {
+                return Err(format!(
+                    "incompatible parts: FDTS {} ({}) and {} ({})",
+                    first.sizes_string(),
+                    first.criterion,
+                    p.sizes_string(),
+                    p.criterion
+                ));
+            }
+        }
+        if let Some(p) = parts.iter().find(|p| !p.complete) {
+            return Err(format!(
+                "incomplete part{}",
+                p.shard.map_or(String::new(), |s| format!(" (shard {})", s))
+            ));
+        }
+        if parts.iter().all(|p| p.shard.is_some()) {
+            let count = first.shard.unwrap().count;
+            if let Some(p) = parts.iter().find(|p| p.shard.unwrap().count != count) {
+                return Err(format!("mixed shard counts {} and {}", count, p.shard.unwrap().count));
+            }
+            let missing = (0..count)
+                .filter(|&i| !parts.iter().any(|p| p.shard.unwrap().index == i))
+                .collect_vec();
+            if !missing.is_empty() {
+                return Err(format!("missing shards {:?} of {}", missing, count));
+            }
+        }
+
+        let mut f = FDTS::new_empty(&first.sizes);
+        f.criterion = first.criterion.clone();
+        for p in parts {
+            f.merged_from.push(match p.shard {
+                Some(s) => format!("shard {}", s),
+                None => "full".into(),
+            });
+        }
+        let dice = parts
+            .iter()
+            .flat_map(|p| p.dice.iter())
+            .sorted_unstable_by(|a, b| a.word.cmp(&b.word));
+        for d in dice {
+            f.insert_dice_tuple(d.clone());
         }
         Ok(f)
     }
 
-    /// Add a dice tuple and all the prefixes
-    pub fn insert_dice_tuple(&mut self, d: DiceTuple) {
+    /// Add a dice tuple and all the prefixes, unless already present.
+    /// Returns whether the tuple was newly inserted.
+    pub fn insert_dice_tuple(&mut self, d: DiceTuple) -> bool {
+        if self.contains(&d) {
+            return false;
+        }
         for i in 0..=self.total {
             self.prefixes.insert(d.word[0..i].into());
         }
         self.dice.push(d);
+        true
+    }
+
+    /// Whether the dice tuple is present (uses the prefix index)
+    pub fn contains(&self, d: &DiceTuple) -> bool {
+        // Prefixes of full length are exactly the present words
+        d.word.len() == self.total && self.prefixes.contains(&d.word)
+    }
+
+    /// All dice tuples present in `self` or `other` (sorted by word), of the same sizes and criterion.
+    /// The result is complete if either side is complete for the full problem, or for the shard both sides are of,
+    /// or if the two sides are the complete halves of a split into 2 shards. A union spanning different shards
+    /// otherwise has no shard and is not complete.
+    pub fn union(&self, other: &FDTS) -> FDTS {
+        assert_eq!(self.sizes, other.sizes);
+        assert_eq!(self.criterion, other.criterion);
+        let mut f = FDTS::new_empty(&self.sizes);
+        f.criterion = self.criterion.clone();
+        let complete_full = |f: &FDTS| f.complete && f.shard.is_none();
+        let (complete, shard) = match (self.shard, other.shard) {
+            // A complete full result covers any shard
+            _ if complete_full(self) || complete_full(other) => (true, None),
+            (s, t) if s == t => (self.complete || other.complete, s),
+            (Some(s), Some(t)) if self.complete && other.complete && s.count == 2 && t.count == 2 => (true, None),
+            _ => (false, None),
+        };
+        f.complete = complete;
+        f.shard = shard;
+        for d in self
+            .dice
+            .iter()
+            .chain(other.dice.iter())
+            .sorted_unstable_by(|a, b| a.word.cmp(&b.word))
+        {
+            f.insert_dice_tuple(d.clone());
+        }
+        f
+    }
+
+    /// Dice tuples of `self` also present in `other`, of the same sizes and criterion.
+    /// The result is complete only if both sides are (see `set_coverage_from`).
+    pub fn intersection(&self, other: &FDTS) -> FDTS {
+        assert_eq!(self.sizes, other.sizes);
+        assert_eq!(self.criterion, other.criterion);
+        let mut f = FDTS::new_empty(&self.sizes);
+        f.criterion = self.criterion.clone();
+        f.set_coverage_from(&[self, other]);
+        for d in self.dice.iter().filter(|d| other.contains(d)) {
+            f.insert_dice_tuple(d.clone());
+        }
+        f
+    }
+
+    /// Dice tuples of `self` not present in `other`, of the same sizes and criterion.
+    /// The result is complete only if both sides are (see `set_coverage_from`).
+    pub fn difference(&self, other: &FDTS) -> FDTS {
+        assert_eq!(self.sizes, other.sizes);
+        assert_eq!(self.criterion, other.criterion);
+        let mut f = FDTS::new_empty(&self.sizes);
+        f.criterion = self.criterion.clone();
+        f.set_coverage_from(&[self, other]);
+        for d in self.dice.iter().filter(|d| !other.contains(d)) {
+            f.insert_dice_tuple(d.clone());
+        }
+        f
+    }
+
+    /// Set `complete` and `shard` for a result computed from all of `parts`: complete if all the parts are
+    /// complete for the same shard (or the full problem), which the result then covers.
+    fn set_coverage_from(&mut self, parts: &[&FDTS]) {
+        let shards = parts.iter().filter_map(|p| p.shard).unique().collect_vec();
+        self.complete = parts.iter().all(|p| p.complete) && shards.len() <= 1;
+        self.shard = if self.complete { shards.first().copied() } else { None };
+    }
+
+    /// Relabel the dice within every group of equally-sized dice by the order of their first
+    /// occurrence in `word`. This is the representative that combine keeps of all the relabelings.
+    /// Words are their own representatives if the criterion is not symmetric.
+    pub fn canonical_word(&self, word: &[u8]) -> Word {
+        if !self.criterion.is_symmetric() {
+            return word.into();
+        }
+        let mut relabel: Vec<Option<u8>> = vec![None; self.n()];
+        // Next free label for each group, indexed by the first dice of the group
+        let mut next: Vec<usize> = (0..self.n()).collect();
+        let group_start = |i: usize| (0..=i).find(|&j| self.sizes[j] == self.sizes[i]).unwrap();
+        for &x in word {
+            let x = x as usize;
+            if relabel[x].is_none() {
+                let g = group_start(x);
+                relabel[x] = Some(next[g] as u8);
+                next[g] += 1;
+            }
+        }
+        word.iter().map(|&x| relabel[x as usize].unwrap()).collect()
+    }
+
+    /// Restrict all dice tuples to the given (ascending) subset of dice, relabeled as `0..dice.len()`.
+    /// The result has no duplicates and has the restricted criterion (see `FairnessCriterion::restrict`).
+    /// It is complete only if `self` is complete and not a shard (shards do not carry over to the projection).
+    pub fn project(&self, dice: &[usize]) -> FDTS {
+        self.project_with_counts(dice).0
+    }
+
+    /// Same as `project`, also returning for every projected tuple (in the order of the `dice` field
+    /// of the returned FDTS) how many tuples of `self` restrict to it.
+    pub fn project_with_counts(&self, dice: &[usize]) -> (FDTS, Vec<usize>) {
+        assert!(!dice.is_empty());
+        assert!(dice.windows(2).all(|w| w[0] < w[1]), "projected dice need to be ascending");
+        assert!(dice.iter().all(|&i| i < self.n()));
+        let mut relabel = vec![None; self.n()];
+        for (j, &i) in dice.iter().enumerate() {
+            relabel[i] = Some(j as u8);
+        }
+
+        let mut counts = HashMap::<Word, usize>::default();
+        for d in &self.dice {
+            let w: Word = d.word.iter().filter_map(|&x| relabel[x as usize]).collect();
+            *counts.entry(w).or_default() += 1;
+        }
+
+        let mut f = FDTS::new_empty(&dice.iter().map(|&i| self.sizes[i]).collect_vec());
+        f.criterion = self.criterion.restrict(dice);
+        f.complete = self.complete && self.shard.is_none();
+        let mut res_counts = vec![];
+        for (w, c) in counts.into_iter().sorted_unstable() {
+            f.insert_dice_tuple(DiceTuple::from_word(&f, &w));
+            res_counts.push(c);
+        }
+        (f, res_counts)
     }
 
     /// Number of dice in FDTS
@@ -94,7 +328,6 @@ impl FDTS {
     /// Create a MappedFDTS wrapping this FDTS (borrows non-mutably)
     pub fn mapped_as<'a>(&'a self, positions: &[isize]) -> MappedFDTS<'a> {
         let map: Vec<_> = (0..self.n())
-            .into_iter()
             .map(|x| positions.iter().position(|&i| i == x as isize).expect("invalid positions"))
             .collect();
         MappedFDTS::new(self, &map, positions.len())
@@ -104,7 +337,7 @@ impl FDTS {
 #[cfg(test)]
 mod test {
     use crate::fdts::FDTS;
-    use crate::DiceTuple;
+    use crate::{subset_fairness, Criterion, DiceTuple, Shard, WeightedFairness};
 
     #[test]
     fn test_basic() {
@@ -116,4 +349,151 @@ mod test {
         assert!(d1 == d2);
         assert!(d1.as_string() == "ACBBCABCC")
     }
+
+    #[test]
+    fn test_set_ops() {
+        let from_words = |words: &[&str]| {
+            let mut f = FDTS::new_empty(&[2, 2]);
+            f.criterion = subset_fairness(2);
+            for w in words {
+                f.insert_dice_tuple(DiceTuple::from_string(&f, w));
+            }
+            f
+        };
+        let strings = |f: &FDTS| f.dice.iter().map(|d| d.as_string()).collect::<Vec<_>>();
+        let a = from_words(&["ABBA", "BAAB", "ABBA"]);
+        let b = from_words(&["BAAB", "ABAB"]);
+        assert_eq!(strings(&a), ["ABBA", "BAAB"]);
+        assert!(a.contains(&DiceTuple::from_string(&a, "BAAB")));
+        assert!(!a.contains(&DiceTuple::from_string(&a, "ABAB")));
+        assert_eq!(strings(&a.union(&b)), ["ABAB", "ABBA", "BAAB"]);
+        assert_eq!(strings(&a.intersection(&b)), ["BAAB"]);
+        assert_eq!(strings(&a.difference(&b)), ["ABBA"]);
+        assert_eq!(strings(&b.difference(&a)), ["ABAB"]);
+        assert!(a.difference(&a).dice.is_empty());
+    }
+
+    #[test]
+    fn test_set_ops_coverage() {
+        let part = |complete: bool, shard: Option<Shard>| {
+            let mut f = FDTS::new_empty(&[2, 2]);
+            f.criterion = subset_fairness(2);
+            f.insert_dice_tuple(DiceTuple::from_string(&f, "ABBA"));
+            f.complete = complete;
+            f.shard = shard;
+            f
+        };
+        let coverage = |f: FDTS| (f.complete, f.shard);
+        let full = part(true, None);
+        let stopped = part(false, None);
+        let shard0 = part(true, Some(Shard::new(0, 2)));
+        let shard1 = part(true, Some(Shard::new(1, 2)));
+
+        assert_eq!(coverage(full.union(&stopped)), (true, None));
+        assert_eq!(coverage(stopped.union(&shard0)), (false, None));
+        assert_eq!(
+            coverage(part(false, Some(Shard::new(0, 2))).union(&shard0)),
+            (true, Some(Shard::new(0, 2)))
+        );
+        // Complete shards make up the full problem only if there are no others
+        assert_eq!(coverage(shard0.union(&shard1)), (true, None));
+        let third = part(true, Some(Shard::new(1, 3)));
+        assert_eq!(coverage(shard0.union(&third)), (false, None));
+        assert_eq!(coverage(part(true, Some(Shard::new(0, 3))).union(&third)), (false, None));
+        assert_eq!(coverage(shard0.union(&full)), (true, None));
+        assert_eq!(coverage(stopped.union(&stopped)), (false, None));
+
+        assert_eq!(coverage(full.intersection(&full)), (true, None));
+        assert_eq!(coverage(full.intersection(&stopped)), (false, None));
+        assert_eq!(coverage(shard0.intersection(&full)), (true, Some(Shard::new(0, 2))));
+        assert_eq!(coverage(shard0.intersection(&shard1)), (false, None));
+        assert_eq!(coverage(full.difference(&stopped)), (false, None));
+        assert_eq!(coverage(shard1.difference(&shard1)), (true, Some(Shard::new(1, 2))));
+
+        assert_eq!(coverage(full.project(&[0])), (true, None));
+        assert_eq!(coverage(stopped.project(&[0])), (false, None));
+        assert_eq!(coverage(shard0.project(&[0])), (false, None));
+    }
+
+    #[test]
+    fn test_project() {
+        let mut f = FDTS::new_empty(&[1, 2, 2]);
+        f.criterion = subset_fairness(3);
+        for w in ["ABCCB", "BACCB", "CABBC"] {
+            f.insert_dice_tuple(DiceTuple::from_string(&f, w));
+        }
+        let (p, counts) = f.project_with_counts(&[1, 2]);
+        assert_eq!(p.sizes, [2, 2]);
+        assert_eq!(p.criterion, subset_fairness(2));
+        assert_eq!(p.dice.iter().map(|d| d.as_string()).collect::<Vec<_>>(), ["ABBA", "BAAB"]);
+        assert_eq!(counts, [2, 1]);
+        let p0 = f.project(&[0]);
+        assert_eq!(p0.sizes, [1]);
+        assert_eq!(p0.criterion, subset_fairness(1));
+        assert_eq!(p0.dice.len(), 1);
+    }
+
+    #[test]
+    fn test_json() {
+        let mut f = FDTS::new_empty(&[2, 2]);
+        f.criterion = subset_fairness(2);
+        f.insert_dice_tuple(DiceTuple::from_string(&f, "ABBA"));
+        let mut buf = vec![];
+        f.write_json(&mut buf).unwrap();
+        assert!(String::from_utf8_lossy(&buf).contains(r#""criterion": "fair2""#));
+        assert_eq!(FDTS::from_json(buf.as_slice()).unwrap(), f);
+
+        // Files written before the criteria only have `fair_up_to`
+        let legacy = r#"{"sizes": [2, 2], "fair_up_to": 2, "words": ["ABBA"]}"#;
+        assert_eq!(FDTS::from_json(legacy.as_bytes()).unwrap(), f);
+        let unknown = r#"{"sizes": [2, 2], "criterion": "unfair", "words": ["ABBA"]}"#;
+        assert!(FDTS::from_json(unknown.as_bytes()).is_err());
+
+        // Weighted criteria are stored with their parameters
+        let mut w = FDTS::new_empty(&[2, 2]);
+        w.criterion = Criterion::new(WeightedFairness::new(2, vec![3, 1]));
+        w.insert_dice_tuple(DiceTuple::from_string(&w, "ABAB"));
+        let mut buf = vec![];
+        w.write_json(&mut buf).unwrap();
+        assert_eq!(FDTS::from_json(buf.as_slice()).unwrap(), w);
+        let text = String::from_utf8(buf)
+            .unwrap()
+            .replace(&w.criterion.tag(), "weighted0000000000000000");
+        assert!(FDTS::from_json(text.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_canonical() {
+        let f = FDTS::new_empty(&[1, 2, 2, 3]);
+        let w = |s: &str| DiceTuple::from_string(&f, s).word;
+        assert_eq!(f.canonical_word(&w("CBDADBDC")), w("BCDADCDB"));
+        assert_eq!(f.canonical_word(&w("BCDADCDB")), w("BCDADCDB"));
+        assert_eq!(f.canonical_word(&w("ADDDBCCB")), w("ADDDBCCB"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut parts = vec![];
+        for (i, words) in [&["ABBA", "ABAB"][..], &["BAAB", "ABBA"][..]].iter().enumerate() {
+            let mut f = FDTS::new_empty(&[2, 2]);
+            f.criterion = subset_fairness(1);
+            f.shard = Some(Shard::new(i, 2));
+            for w in words.iter() {
+                f.insert_dice_tuple(DiceTuple::from_string(&f, w));
+            }
+            parts.push(f);
+        }
+        let m = FDTS::merge(&parts).unwrap();
+        assert_eq!(m.dice.iter().map(|d| d.as_string()).collect::<Vec<_>>(), ["ABAB", "ABBA", "BAAB"]);
+        assert_eq!(m.shard, None);
+        assert_eq!(m.merged_from, ["shard 0/2", "shard 1/2"]);
+
+        assert!(FDTS::merge(&parts[..1]).unwrap_err().contains("missing shards"));
+        let mut other = parts[1].clone();
+        other.criterion = subset_fairness(2);
+        assert!(FDTS::merge(&[parts[0].clone(), other]).is_err());
+        let mut stopped = parts[1].clone();
+        stopped.complete = false;
+        assert!(FDTS::merge(&[parts[0].clone(), stopped]).unwrap_err().contains("incomplete"));
+    }
 }
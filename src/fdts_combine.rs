@@ -1,16 +1,308 @@
-use std::{sync::Mutex, time::Instant};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::{
+    io,
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
 
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use rustc_hash::FxHashMap as HashMap;
 
-use indicatif::{ProgressBar, ProgressStyle};
 use itertools::Itertools;
 use log::{debug, info};
+use serde::Serialize;
 
-use crate::{is_word_permutation_fair_up_to, subset_word, DiceTuple, MappedFDTS, Word, FDTS};
+use crate::binning::{bin_words, estimated_bins_memory, Bins, SpilledBins};
+use crate::progress::{Progress, ProgressUpdate, SilentProgress};
+use crate::{Criterion, DiceTuple, MappedFDTS, PrefixCheck, Shard, Word, FDTS};
+
+/// Upper bound on the number of spill partitions
+const MAX_SPILL_PARTITIONS: usize = 1024;
+
+/// Process the (key, w1) work units in parallel only if there are at least this many per thread,
+/// otherwise also parallelize over the words w2 of every unit
+const NESTED_PARALLELISM_FACTOR: usize = 8;
+
+/// Minimum time between two progress updates of one thread
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Results and not yet reported progress of one thread (or fold chunk) of combine
+struct Accumulator<'a> {
+    results: Vec<Word>,
+    /// Number of fair words found (also those not stored in count-only mode)
+    /// and how many of them are already counted in `ProgressCounters`
+    found: u64,
+    reported_found: u64,
+    /// Candidates and dice pairs processed since the last flush to `ProgressCounters`
+    candidates: u64,
+    pairs: u64,
+    /// All dice pairs processed, not reset by flushes
+    pairs_seen: u64,
+    /// The checking views in the order they are tried, `checking[j]` is the view `order[j]` of the combine
+    checking: Vec<MappedFDTS<'a>>,
+    order: Vec<usize>,
+    /// Prefix checks and prunes made by every checking view, in the original order of the views
+    tests: Vec<u64>,
+    prunes: Vec<u64>,
+    /// Prunes by the criterion's `FairnessCriterion::prefix_check`
+    criterion_prunes: u64,
+    last_flush: Instant,
+    /// Some work was skipped because the combine was stopped
+    stopped: bool,
+}
+
+impl<'a> Accumulator<'a> {
+    fn new(checking: &[MappedFDTS<'a>]) -> Self {
+        Accumulator {
+            results: vec![],
+            found: 0,
+            reported_found: 0,
+            candidates: 0,
+            pairs: 0,
+            pairs_seen: 0,
+            checking: checking.to_vec(),
+            order: (0..checking.len()).collect(),
+            tests: vec![0; checking.len()],
+            prunes: vec![0; checking.len()],
+            criterion_prunes: 0,
+            last_flush: Instant::now(),
+            stopped: false,
+        }
+    }
+
+    fn merge(mut self, mut other: Accumulator<'a>) -> Accumulator<'a> {
+        if self.results.len() < other.results.len() {
+            std::mem::swap(&mut self.results, &mut other.results);
+        }
+        self.results.append(&mut other.results);
+        self.found += other.found;
+        self.reported_found += other.reported_found;
+        self.candidates += other.candidates;
+        self.pairs += other.pairs;
+        self.pairs_seen += other.pairs_seen;
+        for i in 0..self.order.len() {
+            self.tests[i] += other.tests[i];
+            self.prunes[i] += other.prunes[i];
+        }
+        self.criterion_prunes += other.criterion_prunes;
+        self.last_flush = self.last_flush.min(other.last_flush);
+        self.stopped |= other.stopped;
+        self
+    }
+
+    /// Add the counts of one interleaving with the current order of the views
+    fn count_prunes(&mut self, interleaved: &Interleaved, all_checking: &[MappedFDTS<'a>]) {
+        for (j, &i) in self.order.iter().enumerate() {
+            self.tests[i] += interleaved.tests[j];
+            self.prunes[i] += interleaved.prunes[j];
+        }
+        self.criterion_prunes += interleaved.criterion_prunes;
+        self.pairs_seen += 1;
+        // Reorder often at first, then ever more rarely as the rates settle
+        if self.pairs_seen.is_power_of_two() {
+            self.reorder(all_checking);
+        }
+    }
+
+    /// Try the checking views with the highest rate of prunes per check first.
+    /// (Not the most prunes, as the views tried first get the most chances to prune.)
+    fn reorder(&mut self, all_checking: &[MappedFDTS<'a>]) {
+        let rate = |i: usize| self.prunes[i] as f64 / self.tests[i].max(1) as f64;
+        let mut order = self.order.clone();
+        order.sort_by(|&a, &b| rate(b).total_cmp(&rate(a)));
+        if order != self.order {
+            self.checking = order.iter().map(|&i| all_checking[i].clone()).collect();
+            self.order = order;
+        }
+    }
+}
+
+/// Progress totals shared by all threads, only touched when an `Accumulator` is flushed
+#[derive(Default)]
+struct ProgressCounters {
+    pairs_total: AtomicU64,
+    pairs: AtomicU64,
+    candidates: AtomicU64,
+    results: AtomicU64,
+}
+
+impl ProgressCounters {
+    /// Move the pending counts of `acc` to the totals and report them
+    fn flush(&self, acc: &mut Accumulator<'_>, progress: &dyn Progress, t0: Instant) -> ProgressUpdate {
+        let pairs = self.pairs.fetch_add(acc.pairs, Ordering::Relaxed) + acc.pairs;
+        let c = self.candidates.fetch_add(acc.candidates, Ordering::Relaxed) + acc.candidates;
+        let new_results = acc.found - acc.reported_found;
+        let r = self.results.fetch_add(new_results, Ordering::Relaxed) + new_results;
+        acc.reported_found = acc.found;
+        acc.pairs = 0;
+        acc.candidates = 0;
+        acc.last_flush = Instant::now();
+        let update = ProgressUpdate {
+            pairs_done: pairs,
+            pairs_total: self.pairs_total.load(Ordering::Relaxed),
+            candidates: c,
+            results: r,
+            elapsed_secs: t0.elapsed().as_secs_f64(),
+        };
+        progress.update(&update);
+        update
+    }
+}
+
+/// Shared flag to stop running combines early, e.g. from a signal handler or another thread
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask all combines using this token to stop, they return incomplete results
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// How the combine work is spread over threads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Parallelism {
+    /// Use the global rayon thread pool
+    #[default]
+    Global,
+    /// Use a dedicated thread pool with the given number of threads
+    Threads(usize),
+    /// Run strictly in the calling thread, e.g. for profiling and reproducibility
+    Sequential,
+}
+
+/// Tuning options for [`FDTS::new_combined_with`]
+#[derive(Debug, Clone, Default)]
+pub struct CombineOptions {
+    /// Approximate memory budget (in bytes) for the binned input words.
+    /// When the bins would exceed it, the words are spilled to disk partitioned by bin
+    /// and the partitions are combined one at a time (default: unlimited, all in memory)
+    pub memory_budget: Option<usize>,
+    /// Directory for the spill files (default: the system temp dir)
+    pub spill_dir: Option<PathBuf>,
+    /// Only process the work units of this shard, producing a partial result (default: all)
+    pub shard: Option<Shard>,
+    /// Threads to run the combine in (default: the global rayon pool)
+    pub parallelism: Parallelism,
+    /// Where to report progress (default: nowhere)
+    pub progress: Option<Arc<dyn Progress>>,
+    /// Stop early when cancelled, returning a result marked as not `complete`
+    pub cancel: Option<CancellationToken>,
+    /// Stop early at this wall-clock time, returning a result marked as not `complete`.
+    /// A deadline rather than a duration, so it bounds the whole computation of `load_or_compute`
+    /// and `find_one` including all the subproblems (default: no limit)
+    pub deadline: Option<Instant>,
+    /// Stop early once this many fair dice tuples are found, returning (at most) that many
+    /// in a result marked as not `complete`, unless there happened to be no more (default: find all)
+    pub limit: Option<usize>,
+    /// Only count the fair dice tuples in `CombineStats::results` without storing them.
+    /// The returned FDTS has no dice and is not `complete` (default: store all)
+    pub count_only: bool,
+}
+
+impl CombineOptions {
+    /// Why the computation needs to stop now: cancelled or past the deadline (but not by the `limit`)
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        if self.cancel.as_ref().is_some_and(|c| c.is_cancelled()) {
+            Some(StopReason::Cancelled)
+        } else if self.deadline.is_some_and(|d| Instant::now() >= d) {
+            Some(StopReason::TimeBudget)
+        } else {
+            None
+        }
+    }
+}
+
+/// Why a combine was stopped before processing all the dice pairs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum StopReason {
+    Cancelled,
+    TimeBudget,
+    Limit,
+}
+
+/// Counts and timings of one combine, see [`FDTS::new_combined_with_stats`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CombineStats {
+    pub sizes: Vec<usize>,
+    /// Tag of the fairness criterion
+    pub criterion: String,
+    /// Number of dice tuples of the two combined and the checking inputs, in that order
+    pub input_dice: Vec<usize>,
+    /// Number of distinct words on the common dice of either input, and of those common to both
+    /// (summed over the spill partitions)
+    pub bins1: usize,
+    pub bins2: usize,
+    pub common_bins: usize,
+    pub partitions: usize,
+    /// Dice pairs interleaved, and in total (less when stopped early)
+    pub pairs: u64,
+    pub pairs_total: u64,
+    /// Interleavings that passed all checking views and were tested for fairness
+    pub candidates: u64,
+    /// Interleaving branches cut by a checking view, in total and by each view (in the order of the inputs)
+    pub prunes: u64,
+    pub prunes_by_checking: Vec<u64>,
+    /// Interleaving branches cut by the fairness criterion itself (see `FairnessCriterion::prefix_check`)
+    pub criterion_prunes: u64,
+    pub results: usize,
+    /// All the dice pairs were processed (and the inputs were complete), so `results` is exact
+    pub complete: bool,
+    /// Set when the combine was stopped early (even if no work was left by then)
+    pub stopped: Option<StopReason>,
+    pub elapsed_secs: f64,
+}
+
+/// Words generated by one interleaving, with the number of prefixes tested and pruned by each checking view
+/// and pruned by the criterion
+#[derive(Debug, Default)]
+pub(crate) struct Interleaved {
+    pub words: Vec<Word>,
+    pub tests: Vec<u64>,
+    pub prunes: Vec<u64>,
+    pub criterion_prunes: u64,
+}
+
+/// What the prefixes of the interleavings are checked against
+struct PrefixFilter<'v, 'a> {
+    checking: &'v [MappedFDTS<'a>],
+    criterion: Option<&'v dyn PrefixCheck>,
+}
 
 impl FDTS {
-    pub fn new_combined(d1: MappedFDTS<'_>, d2: MappedFDTS<'_>, checking: &[MappedFDTS<'_>], fair_up_to: usize) -> Self {
+    pub fn new_combined(d1: MappedFDTS<'_>, d2: MappedFDTS<'_>, checking: &[MappedFDTS<'_>], criterion: &Criterion) -> Self {
+        Self::new_combined_with(d1, d2, checking, criterion, &CombineOptions::default()).expect("in-memory combine does no I/O")
+    }
+
+    pub fn new_combined_with(
+        d1: MappedFDTS<'_>,
+        d2: MappedFDTS<'_>,
+        checking: &[MappedFDTS<'_>],
+        criterion: &Criterion,
+        opts: &CombineOptions,
+    ) -> io::Result<Self> {
+        Ok(Self::new_combined_with_stats(d1, d2, checking, criterion, opts)?.0)
+    }
+
+    /// Combine as `new_combined_with`, also returning statistics of the search
+    pub fn new_combined_with_stats<'c>(
+        d1: MappedFDTS<'_>,
+        d2: MappedFDTS<'_>,
+        checking: &[MappedFDTS<'c>],
+        criterion: &Criterion,
+        opts: &CombineOptions,
+    ) -> io::Result<(Self, CombineStats)> {
+        let t0 = Instant::now();
         assert!(d1.is_compatible_with(&d2));
         for c in checking {
             assert!(d1.is_compatible_with(c));
@@ -27,12 +319,16 @@ impl FDTS {
             .collect();
 
         let mut f = FDTS::new_empty(&sizes);
-        f.fair_up_to = fair_up_to;
-        assert!(f.fair_up_to <= f.n());
-        assert!(f.fair_up_to >= d1.fdts.fair_up_to);
-        assert!(f.fair_up_to >= d2.fdts.fair_up_to);
-        for c in checking {
-            assert!(f.fair_up_to >= c.fdts.fair_up_to);
+        f.criterion = criterion.clone();
+        f.shard = opts.shard;
+        // The inputs need to contain all the restrictions of the fair dice tuples
+        for m in [&d1, &d2].iter().copied().chain(checking) {
+            assert_eq!(
+                m.fdts.criterion,
+                criterion.restrict(&m.map),
+                "input {} of a wrong criterion",
+                m.sizes_string()
+            );
         }
 
         let bin_indices: Vec<_> = d1.map.iter().cloned().filter(|i| d2.map.contains(i)).collect();
@@ -44,86 +340,210 @@ impl FDTS {
             checking.iter().map(|c| { c.sizes_string() }).collect_vec()
         );
 
+        let mut stats = CombineStats {
+            sizes: f.sizes.clone(),
+            criterion: criterion.tag(),
+            input_dice: [&d1, &d2].iter().copied().chain(checking).map(|m| m.fdts.dice.len()).collect(),
+            complete: true,
+            ..Default::default()
+        };
         if d1.fdts.dice.is_empty() || d2.fdts.dice.is_empty() || checking.iter().any(|c| c.fdts.dice.is_empty()) {
             debug!(" .. some of the inputs are empty, returning empty FDTS {}.", f.sizes_string());
-            return f;
-        }
-
-        let mut bins1 = HashMap::<Word, Vec<Word>>::default();
-        for w in d1.iterate_words() {
-            bins1.entry(subset_word(&w, &bin_indices)).or_default().push(w);
-        }
-
-        let mut bins2 = HashMap::<Word, Vec<Word>>::default();
-        for w in d2.iterate_words() {
-            bins2.entry(subset_word(&w, &bin_indices)).or_default().push(w);
-        }
-
-        let common_keys = bins1.keys().filter(|&bw| bins2.contains_key(bw)).collect_vec();
-        let total_pairs: usize = common_keys.iter().map(|&bw| bins1[bw].len() * bins2[bw].len()).sum();
-
-        let mut key_w1_pairs = vec![];
-        for c in &common_keys {
-            for w1 in &bins1[*c] {
-                key_w1_pairs.push((c, w1));
-            }
+            stats.elapsed_secs = t0.elapsed().as_secs_f64();
+            return Ok((f, stats));
         }
 
+        let bins_memory = estimated_bins_memory(&d1, &d2);
+        let partitions = match opts.memory_budget {
+            Some(budget) if bins_memory > budget => bins_memory.div_ceil(budget.max(1)).min(MAX_SPILL_PARTITIONS),
+            _ => 1,
+        };
         debug!(
-            " .. combining {} and {} dice with common positions {:?} and {} bins, interleaving total {} dice pairs, fair up to {}",
+            " .. combining {} and {} dice with common positions {:?} in {} partition(s) (estimated {} MB of bins), {}, shard {}",
             d1.fdts.dice.len(),
             d2.fdts.dice.len(),
             &bin_indices,
-            common_keys.len(),
-            total_pairs,
-            f.fair_up_to,
-        );
-        let bar = ProgressBar::new(total_pairs as u64);
-        bar.set_style(
-            ProgressStyle::default_bar()
-                .template("combining: {percent}%|{wide_bar}| {pos}/{len} pairs [{elapsed}<{eta}] {msg}")
-                .progress_chars("##-"),
+            partitions,
+            bins_memory >> 20,
+            f.criterion,
+            opts.shard.map_or("all".into(), |s| s.to_string()),
         );
-        let candidates = Mutex::new(0usize);
-        let res = Mutex::new(vec![]);
-        let t0 = Instant::now();
 
-        key_w1_pairs.par_iter().for_each(|(&bw, w1)| {
-            let local_process = |w1, w2| {
-                let mut local_c = 0;
-                let mut local_res = Vec::new();
-                for wi in f.interleave_words(w1, w2, &checking, &bin_indices, true) {
-                    local_c += 1;
-                    if is_word_permutation_fair_up_to(&wi, (0..f.n() as u8).collect::<Word>(), f.fair_up_to) {
-                        local_res.push(DiceTuple::from_word(&f, &wi));
+        let progress = opts.progress.as_deref().unwrap_or(&SilentProgress);
+        progress.start(&f.sizes);
+        stats.partitions = partitions;
+        let counters = ProgressCounters::default();
+        // Cancellation and time are checked by the threads on every flush, so they stop within
+        // about PROGRESS_INTERVAL, the limit is checked on every result found
+        let stopped: OnceLock<StopReason> = OnceLock::new();
+        let found = AtomicUsize::new(0);
+        let check_stop = || {
+            if let Some(reason) = opts.stop_reason() {
+                let _ = stopped.set(reason);
+            }
+        };
+        check_stop();
+        if opts.limit == Some(0) {
+            let _ = stopped.set(StopReason::Limit);
+        }
+
+        let criterion_check = f.criterion.prefix_check(&f.sizes);
+        let local_process = |acc: &mut Accumulator<'c>, w1: &Word, w2: &Word| {
+            if stopped.get().is_some() {
+                acc.stopped = true;
+                return;
+            }
+            let interleaved = f.interleave_words_counted(
+                w1,
+                w2,
+                &acc.checking,
+                criterion_check.as_deref(),
+                &bin_indices,
+                f.criterion.is_symmetric(),
+            );
+            acc.count_prunes(&interleaved, checking);
+            for wi in interleaved.words {
+                acc.candidates += 1;
+                if f.criterion.is_fair(&wi, f.n()) {
+                    acc.found += 1;
+                    if !opts.count_only {
+                        acc.results.push(wi);
+                    }
+                    if opts.limit.is_some_and(|l| found.fetch_add(1, Ordering::Relaxed) + 1 >= l) {
+                        let _ = stopped.set(StopReason::Limit);
                     }
                 }
-                let mut c = candidates.lock().unwrap();
-                let mut r = res.lock().unwrap();
-                *c += local_c;
-                r.extend_from_slice(&mut local_res);
-                //bar.inc((bins2[bw].len()) as u64);
-                bar.inc(1);
-                bar.set_message(format!(
-                    "{} results, {} candidates, {:.2} cands/s",
-                    r.len(),
-                    *c,
-                    (*c as f64) / t0.elapsed().as_secs_f64()
-                ));
-            };
-            // If key_w1_pairs is small, also iterate over bins2[bw] in parallel
-            if key_w1_pairs.len() > 64 {
-                for w2 in &bins2[bw] {
-                    local_process(w1, w2);
+            }
+            acc.pairs += 1;
+            if acc.last_flush.elapsed() >= PROGRESS_INTERVAL {
+                counters.flush(acc, progress, t0);
+                check_stop();
+            }
+        };
+
+        let process_bins = |bins1: &Bins, bins2: &Bins| -> Accumulator<'c> {
+            let common_keys = bins1.keys().filter(|&bw| bins2.contains_key(bw)).collect_vec();
+
+            let mut key_w1_pairs = vec![];
+            for &c in &common_keys {
+                for w1 in &bins1[c] {
+                    if opts.shard.is_none_or(|s| s.owns(w1)) {
+                        key_w1_pairs.push((c, w1));
+                    }
+                }
+            }
+            let total_pairs: usize = key_w1_pairs.iter().map(|&(bw, _)| bins2[bw].len()).sum();
+            counters.pairs_total.fetch_add(total_pairs as u64, Ordering::Relaxed);
+            debug!(" .. {} bins, interleaving total {} dice pairs", common_keys.len(), total_pairs);
+
+            // If there are few (key, w1) units for the threads, also iterate over bins2[bw] in parallel
+            let nested = opts.parallelism != Parallelism::Sequential
+                && key_w1_pairs.len() < NESTED_PARALLELISM_FACTOR * rayon::current_num_threads();
+            let process_unit = |mut acc: Accumulator<'c>, &(bw, w1): &(&Word, &Word)| {
+                if nested {
+                    let inner = bins2[bw]
+                        .par_iter()
+                        .fold(
+                            || Accumulator::new(checking),
+                            |mut acc, w2| {
+                                local_process(&mut acc, w1, w2);
+                                acc
+                            },
+                        )
+                        .reduce(|| Accumulator::new(checking), Accumulator::merge);
+                    acc = acc.merge(inner);
+                } else {
+                    for w2 in &bins2[bw] {
+                        local_process(&mut acc, w1, w2);
+                    }
                 }
+                acc
+            };
+            if opts.parallelism == Parallelism::Sequential {
+                key_w1_pairs.iter().fold(Accumulator::new(checking), process_unit)
             } else {
-                bins2[bw].par_iter().for_each(|w2| local_process(w1, w2));
+                key_w1_pairs
+                    .par_iter()
+                    .fold(|| Accumulator::new(checking), process_unit)
+                    .reduce(|| Accumulator::new(checking), Accumulator::merge)
             }
-        });
-        bar.finish();
-        for rd in res.into_inner().unwrap() {
-            f.insert_dice_tuple(rd);
+        };
+
+        let count_bins = |stats: &mut CombineStats, bins1: &Bins, bins2: &Bins| {
+            stats.bins1 += bins1.len();
+            stats.bins2 += bins2.len();
+            stats.common_bins += bins1.keys().filter(|&bw| bins2.contains_key(bw)).count();
+        };
+        let process_all = |stats: &mut CombineStats| -> io::Result<Accumulator<'c>> {
+            if partitions == 1 {
+                let bins1 = bin_words(d1.iterate_words(), &bin_indices);
+                let bins2 = bin_words(d2.iterate_words(), &bin_indices);
+                count_bins(stats, &bins1, &bins2);
+                Ok(process_bins(&bins1, &bins2))
+            } else {
+                let spill_dir = opts.spill_dir.clone().unwrap_or_else(std::env::temp_dir);
+                let spilled = SpilledBins::create(&d1, &d2, &bin_indices, partitions, &spill_dir)?;
+                let mut acc = Accumulator::new(checking);
+                for p in 0..spilled.partitions() {
+                    check_stop();
+                    if stopped.get().is_some() {
+                        acc.stopped = true;
+                        break;
+                    }
+                    let (bins1, bins2) = spilled.load(p)?;
+                    count_bins(stats, &bins1, &bins2);
+                    acc = acc.merge(process_bins(&bins1, &bins2));
+                }
+                Ok(acc)
+            }
+        };
+        let mut acc = match opts.parallelism {
+            Parallelism::Threads(threads) => {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .map_err(io::Error::other)?;
+                pool.install(|| process_all(&mut stats))?
+            }
+            Parallelism::Global | Parallelism::Sequential => process_all(&mut stats)?,
+        };
+        let last = counters.flush(&mut acc, progress, t0);
+        progress.finish(&last);
+        // Threads finish in arbitrary order, sort for reproducible output
+        let mut res = acc.results;
+        res.sort_unstable();
+        let mut found = acc.found as usize;
+        if let Some(limit) = opts.limit.filter(|&l| found > l) {
+            // Other threads may have found more before noticing the limit
+            res.truncate(limit);
+            found = limit;
+            acc.stopped = true;
+        }
+        if acc.stopped {
+            info!(
+                " .. combine stopped early ({:?}), the result is incomplete",
+                stopped.get().expect("stopped for a reason")
+            );
+            f.complete = false;
         }
+        if opts.count_only {
+            f.complete = false;
+        }
+        for w in res {
+            let d = DiceTuple::from_word(&f, &w);
+            f.insert_dice_tuple(d);
+        }
+        stats.pairs = last.pairs_done;
+        stats.pairs_total = last.pairs_total;
+        stats.candidates = last.candidates;
+        stats.prunes = acc.prunes.iter().sum();
+        stats.prunes_by_checking = acc.prunes.clone();
+        stats.criterion_prunes = acc.criterion_prunes;
+        debug!(" .. checking views pruned {:?} of {:?} prefixes", &acc.prunes, &acc.tests);
+        stats.results = found;
+        stats.complete = !acc.stopped;
+        stats.stopped = stopped.get().copied();
+        stats.elapsed_secs = t0.elapsed().as_secs_f64();
 
         debug!(
             " .. created FDTS {:?} with {} fair DiceTuples ({} prefixes)",
@@ -132,7 +552,23 @@ impl FDTS {
             f.prefixes.len(),
         );
 
-        f
+        Ok((f, stats))
+    }
+
+    /// Check `prefix` against the checking views and the criterion, counting the tests and prunes
+    fn prefix_allowed(&self, prefix: &[u8], filter: &PrefixFilter, res: &mut Interleaved) -> bool {
+        for (i, c) in filter.checking.iter().enumerate() {
+            res.tests[i] += 1;
+            if !c.subset_word_in_prefixes(prefix) {
+                res.prunes[i] += 1;
+                return false;
+            }
+        }
+        if filter.criterion.is_some_and(|c| !c.can_extend(prefix)) {
+            res.criterion_prunes += 1;
+            return false;
+        }
+        true
     }
 
     fn _rec_interleave_words(
@@ -140,68 +576,67 @@ impl FDTS {
         out: &mut Word,
         w1: &[u8],
         w2: &[u8],
-        checking: &[MappedFDTS],
+        filter: &PrefixFilter,
         common_dice: &[usize],
-        res: &mut Vec<Word>,
+        res: &mut Interleaved,
     ) {
-        for c in checking {
-            if !c.subset_word_in_prefixes(out) {
-                return;
-            }
+        if !self.prefix_allowed(out, filter, res) {
+            return;
         }
         if w1.is_empty() && w2.is_empty() {
-            res.push(out.clone());
+            res.words.push(out.clone());
             return;
         }
         if w1.is_empty() {
             out.extend_from_slice(w2);
-            self._rec_interleave_words(out, &[], &[], checking, common_dice, res);
+            self._rec_interleave_words(out, &[], &[], filter, common_dice, res);
             out.truncate(out.len() - w2.len());
             return;
         }
         if w2.is_empty() {
             out.extend_from_slice(w1);
-            self._rec_interleave_words(out, &[], &[], checking, common_dice, res);
+            self._rec_interleave_words(out, &[], &[], filter, common_dice, res);
             out.truncate(out.len() - w1.len());
             return;
         }
         if w1[0] == w2[0] {
             out.push(w1[0]);
-            self._rec_interleave_words(out, &w1[1..], &w2[1..], checking, common_dice, res);
+            self._rec_interleave_words(out, &w1[1..], &w2[1..], filter, common_dice, res);
             out.pop();
             return;
         }
         if common_dice.contains(&(w1[0] as usize)) {
             debug_assert!(!common_dice.contains(&(w2[0] as usize)));
             out.push(w2[0]);
-            self._rec_interleave_words(out, &w1, &w2[1..], checking, common_dice, res);
+            self._rec_interleave_words(out, w1, &w2[1..], filter, common_dice, res);
             out.pop();
             return;
         }
         if common_dice.contains(&(w2[0] as usize)) {
             debug_assert!(!common_dice.contains(&(w1[0] as usize)));
             out.push(w1[0]);
-            self._rec_interleave_words(out, &w1[1..], &w2, checking, common_dice, res);
+            self._rec_interleave_words(out, &w1[1..], w2, filter, common_dice, res);
             out.pop();
             return;
         }
         out.push(w1[0]);
-        self._rec_interleave_words(out, &w1[1..], &w2, checking, common_dice, res);
+        self._rec_interleave_words(out, &w1[1..], w2, filter, common_dice, res);
         out.pop();
         out.push(w2[0]);
-        self._rec_interleave_words(out, &w1, &w2[1..], checking, common_dice, res);
+        self._rec_interleave_words(out, w1, &w2[1..], filter, common_dice, res);
         out.pop();
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn _push_rec_lex(
         &self,
         out: &mut Word,
         c: u8,
         w1x: &[u8],
         w2x: &[u8],
-        checking: &[MappedFDTS],
+        filter: &PrefixFilter,
         c_d: &[usize],
-        res: &mut Vec<Word>,
+        res: &mut Interleaved,
         cg: &[bool],
         icg: &[usize],
     ) {
@@ -213,69 +648,71 @@ impl FDTS {
         if !cg[im] {
             let mut cg2: Vec<bool> = cg.into();
             cg2[im] = true;
-            self._rec_interleave_words_lex(out, w1x, w2x, checking, c_d, res, &cg2, icg);
+            self._rec_interleave_words_lex(out, w1x, w2x, filter, c_d, res, &cg2, icg);
         } else {
-            self._rec_interleave_words_lex(out, w1x, w2x, checking, c_d, res, cg, icg);
+            self._rec_interleave_words_lex(out, w1x, w2x, filter, c_d, res, cg, icg);
         }
         out.pop();
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn _rec_interleave_words_lex(
         &self,
         out: &mut Word,
         w1: &[u8],
         w2: &[u8],
-        checking: &[MappedFDTS],
+        filter: &PrefixFilter,
         common_dice: &[usize],
-        res: &mut Vec<Word>,
+        res: &mut Interleaved,
         can_go: &[bool],
         implies_can_go: &[usize],
     ) {
-        for c in checking {
-            if !c.subset_word_in_prefixes(out) {
-                return;
-            }
+        if !self.prefix_allowed(out, filter, res) {
+            return;
         }
         if w1.is_empty() && w2.is_empty() {
-            res.push(out.clone());
+            res.words.push(out.clone());
             return;
         }
         if w1.is_empty() {
             out.extend_from_slice(w2);
-            self._rec_interleave_words(out, &[], &[], checking, common_dice, res);
+            self._rec_interleave_words(out, &[], &[], filter, common_dice, res);
             out.truncate(out.len() - w2.len());
             return;
         }
         if w2.is_empty() {
             out.extend_from_slice(w1);
-            self._rec_interleave_words(out, &[], &[], checking, common_dice, res);
+            self._rec_interleave_words(out, &[], &[], filter, common_dice, res);
             out.truncate(out.len() - w1.len());
             return;
         }
         if can_go.iter().all(|&x| x) {
-            return self._rec_interleave_words(out, w1, w2, checking, common_dice, res);
+            return self._rec_interleave_words(out, w1, w2, filter, common_dice, res);
         }
 
         if w1[0] == w2[0] {
             debug_assert!(common_dice.contains(&(w1[0] as usize)));
-            self._push_rec_lex(out, w1[0], &w1[1..], &w2[1..], checking, common_dice, res, can_go, implies_can_go);
+            self._push_rec_lex(out, w1[0], &w1[1..], &w2[1..], filter, common_dice, res, can_go, implies_can_go);
             return;
         }
         if common_dice.contains(&(w1[0] as usize)) {
             debug_assert!(!common_dice.contains(&(w2[0] as usize)));
-            self._push_rec_lex(out, w2[0], &w1, &w2[1..], checking, common_dice, res, can_go, implies_can_go);
+            self._push_rec_lex(out, w2[0], w1, &w2[1..], filter, common_dice, res, can_go, implies_can_go);
             return;
         }
         if common_dice.contains(&(w2[0] as usize)) {
             debug_assert!(!common_dice.contains(&(w1[0] as usize)));
-            self._push_rec_lex(out, w1[0], &w1[1..], &w2, checking, common_dice, res, can_go, implies_can_go);
+            self._push_rec_lex(out, w1[0], &w1[1..], w2, filter, common_dice, res, can_go, implies_can_go);
             return;
         }
-        self._push_rec_lex(out, w1[0], &w1[1..], &w2, checking, common_dice, res, can_go, implies_can_go);
-        self._push_rec_lex(out, w2[0], &w1, &w2[1..], checking, common_dice, res, can_go, implies_can_go);
+        self._push_rec_lex(out, w1[0], &w1[1..], w2, filter, common_dice, res, can_go, implies_can_go);
+        self._push_rec_lex(out, w2[0], w1, &w2[1..], filter, common_dice, res, can_go, implies_can_go);
     }
 
-    fn interleave_words(
+    /// All interleavings of the (mapped) words `w1` and `w2` of this FDTS's sizes that agree on `common_dice`
+    /// and whose prefixes are consistent with all the `checking` views and may be extended to fair words (by the criterion).
+    /// With `same_lexicographic`, only canonical words w.r.t. relabeling equally-sized dice are generated.
+    pub fn interleave_words(
         &self,
         w1: &Word,
         w2: &Word,
@@ -283,7 +720,32 @@ impl FDTS {
         common_dice: &[usize],
         same_lexicographic: bool,
     ) -> Vec<Word> {
-        let mut res = Vec::new();
+        let criterion_check = self.criterion.prefix_check(&self.sizes);
+        let res = self.interleave_words_counted(w1, w2, checking, criterion_check.as_deref(), common_dice, same_lexicographic);
+        res.words
+    }
+
+    /// As `interleave_words` with the criterion's `prefix_check` prepared by the caller (once for all the words),
+    /// also counting the prefixes tested and pruned by each of the `checking` views
+    pub(crate) fn interleave_words_counted(
+        &self,
+        w1: &Word,
+        w2: &Word,
+        checking: &[MappedFDTS],
+        criterion_check: Option<&dyn PrefixCheck>,
+        common_dice: &[usize],
+        same_lexicographic: bool,
+    ) -> Interleaved {
+        let mut res = Interleaved {
+            words: vec![],
+            tests: vec![0; checking.len()],
+            prunes: vec![0; checking.len()],
+            criterion_prunes: 0,
+        };
+        let filter = PrefixFilter {
+            checking,
+            criterion: criterion_check,
+        };
         let mut buf = Word::new();
         if same_lexicographic {
             let mut size_groups = HashMap::default();
@@ -298,9 +760,9 @@ impl FDTS {
                     implies_can_go[wi[0]] = wi[1];
                 }
             }
-            self._rec_interleave_words_lex(&mut buf, w1, w2, checking, common_dice, &mut res, &can_go, &implies_can_go);
+            self._rec_interleave_words_lex(&mut buf, w1, w2, &filter, common_dice, &mut res, &can_go, &implies_can_go);
         } else {
-            self._rec_interleave_words(&mut buf, w1, w2, checking, common_dice, &mut res);
+            self._rec_interleave_words(&mut buf, w1, w2, &filter, common_dice, &mut res);
         }
         res
     }
@@ -308,15 +770,160 @@ impl FDTS {
 
 #[cfg(test)]
 mod test {
-    use crate::FDTS;
+    use std::time::{Duration, Instant};
+
+    use itertools::Itertools;
+
+    use crate::fdts_combine::{Accumulator, Interleaved};
+    use crate::test::{combine_d6_d6_d6, fair_d6_d6};
+    use crate::{CancellationToken, CombineOptions, DiceTuple, Parallelism, Shard, StopReason, FDTS};
 
     #[test]
     fn test_d6_d6() {
         let d6 = FDTS::new_single(6);
         assert_eq!(d6.dice.len(), 1);
         assert_eq!(d6.prefixes.len(), 7);
-        let d6_d6 = FDTS::new_combined(d6.mapped_as(&[0, -1]), d6.mapped_as(&[-1, 0]), &[], 2);
+        let d6_d6 = fair_d6_d6();
         assert_eq!(d6_d6.dice.len(), 29);
         assert_eq!(d6_d6.prefixes.len(), 200);
+        assert!(d6_d6.dice.windows(2).all(|w| w[0].word < w[1].word));
+    }
+
+    #[test]
+    fn test_spilled_bins() {
+        let combine = |opts: &CombineOptions| combine_d6_d6_d6(opts).0;
+        let in_memory = combine(&CombineOptions::default());
+        assert_eq!(in_memory.dice.len(), 11);
+        let spilled = combine(&CombineOptions {
+            memory_budget: Some(1000),
+            ..Default::default()
+        });
+        assert_eq!(in_memory.dice, spilled.dice);
+    }
+
+    #[test]
+    fn test_parallelism() {
+        let results = [Parallelism::Global, Parallelism::Threads(3), Parallelism::Sequential]
+            .iter()
+            .map(|&parallelism| {
+                combine_d6_d6_d6(&CombineOptions {
+                    parallelism,
+                    ..Default::default()
+                })
+                .0
+            })
+            .collect_vec();
+        assert_eq!(results[0].dice.len(), 11);
+        assert!(results.iter().all(|r| r.dice == results[0].dice));
+    }
+
+    #[test]
+    fn test_sharded() {
+        let combine = |opts: &CombineOptions| combine_d6_d6_d6(opts).0;
+        let full = combine(&CombineOptions::default());
+        let shards = (0..3)
+            .map(|i| {
+                combine(&CombineOptions {
+                    shard: Some(Shard::new(i, 3)),
+                    ..Default::default()
+                })
+            })
+            .collect_vec();
+        assert!(shards.iter().all(|s| s.dice.len() < full.dice.len()));
+        let sorted = |ds: &mut dyn Iterator<Item = &DiceTuple>| ds.map(|d| d.as_string()).sorted().collect_vec();
+        assert_eq!(
+            sorted(&mut shards.iter().flat_map(|s| s.dice.iter())),
+            sorted(&mut full.dice.iter())
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let (f, stats) = combine_d6_d6_d6(&CombineOptions::default());
+        assert_eq!(stats.sizes, [6, 6, 6]);
+        assert_eq!(stats.input_dice, [29, 29, 29]);
+        assert_eq!(stats.partitions, 1);
+        assert_eq!((stats.pairs, stats.pairs_total), (841, 841));
+        assert_eq!(stats.candidates, 2421);
+        assert!(stats.prunes > 0);
+        assert_eq!(stats.prunes_by_checking, [stats.prunes]);
+        assert_eq!(stats.results, f.dice.len());
+        assert!(stats.complete);
+        assert!(stats.common_bins <= stats.bins1.min(stats.bins2));
+
+        let (counted, count_stats) = combine_d6_d6_d6(&CombineOptions {
+            count_only: true,
+            ..Default::default()
+        });
+        assert!(counted.dice.is_empty() && counted.prefixes.is_empty());
+        assert!(!counted.complete);
+        assert_eq!(count_stats.results, 11);
+        assert!(count_stats.complete);
+    }
+
+    #[test]
+    fn test_reorder_checking() {
+        let d6_d6 = fair_d6_d6();
+        let views = [d6_d6.mapped_as(&[0, 1, -1]), d6_d6.mapped_as(&[-1, 0, 1])];
+        let mut acc = Accumulator::new(&views);
+        // The first view prunes more in total but the second one is more selective
+        acc.tests = vec![100, 10];
+        acc.prunes = vec![20, 5];
+        acc.reorder(&views);
+        assert_eq!(acc.order, [1, 0]);
+        assert_eq!(acc.checking, [views[1].clone(), views[0].clone()]);
+
+        // Counts of the reordered views are attributed to the original ones
+        let interleaved = Interleaved {
+            words: vec![],
+            tests: vec![1, 2],
+            prunes: vec![0, 1],
+            criterion_prunes: 0,
+        };
+        acc.count_prunes(&interleaved, &views);
+        assert_eq!((acc.tests.as_slice(), acc.prunes.as_slice()), (&[102, 11][..], &[21, 5][..]));
+    }
+
+    #[test]
+    fn test_stopped() {
+        let combine = |opts: &CombineOptions| combine_d6_d6_d6(opts).0;
+        let full = combine(&CombineOptions {
+            deadline: Some(Instant::now() + Duration::from_secs(3600)),
+            cancel: Some(CancellationToken::new()),
+            ..Default::default()
+        });
+        assert!(full.complete);
+        assert_eq!(full.dice.len(), 11);
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let cancelled = combine(&CombineOptions {
+            cancel: Some(cancel),
+            ..Default::default()
+        });
+        assert!(!cancelled.complete);
+        assert!(cancelled.dice.is_empty());
+
+        let timed_out = combine(&CombineOptions {
+            deadline: Some(Instant::now()),
+            ..Default::default()
+        });
+        assert!(!timed_out.complete);
+
+        let limited = combine_d6_d6_d6(&CombineOptions {
+            limit: Some(2),
+            ..Default::default()
+        });
+        assert_eq!(limited.0.dice.len(), 2);
+        assert!(!limited.0.complete);
+        assert_eq!(limited.1.stopped, Some(StopReason::Limit));
+        assert!(limited.0.dice.iter().all(|d| full.contains(d)));
+        // A limit above the number of results does not make the result incomplete
+        let unlimited = combine(&CombineOptions {
+            limit: Some(100),
+            ..Default::default()
+        });
+        assert!(unlimited.complete);
+        assert_eq!(unlimited.dice, full.dice);
     }
 }
@@ -1,15 +1,36 @@
+mod binning;
+mod brute_force;
+pub mod cache;
+mod constructions;
+mod criterion;
 mod dice_tuple;
 mod fdts;
+mod local_search;
 mod mapped_fdts;
 mod permutations;
+pub mod progress;
+mod shard;
 mod fdts_combine;
 
+#[cfg(test)]
+mod temp_dir;
+
+pub use criterion::{
+    parse_criterion, subset_fairness, Criterion, FairnessCriterion, GoFirstFairness, PrefixCheck, SubsetFairness, TopPlacesFairness,
+    WeightedFairness,
+};
 pub use dice_tuple::DiceTuple;
 pub use fdts::FDTS;
+pub use local_search::LocalSearchOptions;
+pub use fdts_combine::{CancellationToken, CombineOptions, CombineStats, Parallelism, StopReason};
 pub use mapped_fdts::MappedFDTS;
-pub use permutations::{count_permutation_occurences, is_word_permutation_fair, is_word_permutation_fair_up_to};
+pub use shard::Shard;
+pub use permutations::{
+    count_go_first, count_permutation_occurences, count_permutations, count_top_places, is_word_go_first_fair, is_word_permutation_fair,
+    is_word_permutation_fair_up_to, is_word_top_places_fair,
+};
 
-type Word = smallvec::SmallVec<[u8; 64]>;
+pub type Word = smallvec::SmallVec<[u8; 64]>;
 // Consider: type Word = Vec<u8>;
 
 pub fn subset_word(w: &Word, subset: &[usize]) -> Word {
@@ -23,3 +44,85 @@ where
     let data = data.as_ref();
     data.windows(2).all(|w| w[0] <= w[1])
 }
+
+/// FNV-1a hash of `bytes`, the same across versions and platforms (unlike `FxHasher`),
+/// for hashes that end up in file names or decide how work is split between runs
+pub fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| (h ^ b as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::sync::OnceLock;
+
+    use proptest::prelude::*;
+
+    use crate::{stable_hash, subset_fairness, subset_word, CombineOptions, CombineStats, Word, FDTS};
+
+    /// The 29 fair [6,6] dice combined from two single dice, computed once
+    pub(crate) fn fair_d6_d6() -> &'static FDTS {
+        static F: OnceLock<FDTS> = OnceLock::new();
+        F.get_or_init(|| {
+            let d6 = FDTS::new_single(6);
+            FDTS::new_combined(d6.mapped_as(&[0, -1]), d6.mapped_as(&[-1, 0]), &[], &subset_fairness(2))
+        })
+    }
+
+    /// The 11 fair [6,6,6] dice, computed once
+    pub(crate) fn fair_d6_d6_d6() -> &'static FDTS {
+        static F: OnceLock<FDTS> = OnceLock::new();
+        F.get_or_init(|| combine_d6_d6_d6(&CombineOptions::default()).0)
+    }
+
+    /// Combine [6,6,6] fair dice from `fair_d6_d6` on the first two dice, checking the last two
+    pub(crate) fn combine_d6_d6_d6(opts: &CombineOptions) -> (FDTS, CombineStats) {
+        let d6_d6 = fair_d6_d6();
+        FDTS::new_combined_with_stats(
+            d6_d6.mapped_as(&[0, -1, 1]),
+            d6_d6.mapped_as(&[0, 1, -1]),
+            &[d6_d6.mapped_as(&[-1, 0, 1])],
+            &subset_fairness(3),
+            opts,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_stable_hash() {
+        // Reference values of 64-bit FNV-1a
+        assert_eq!(stable_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(stable_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(stable_hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    /// Sorted sizes of 1 to `max_dice` dice with 1 to `max_size` sides and a random word with these letter counts
+    pub(crate) fn sizes_and_word(max_dice: usize, max_size: usize) -> impl Strategy<Value = (Vec<usize>, Word)> {
+        prop::collection::vec(1..=max_size, 1..=max_dice).prop_flat_map(|mut sizes| {
+            sizes.sort_unstable();
+            let word: Vec<u8> = sizes.iter().enumerate().flat_map(|(i, &s)| std::iter::repeat_n(i as u8, s)).collect();
+            (Just(sizes), Just(word).prop_shuffle().prop_map(Word::from_vec))
+        })
+    }
+
+    /// Ascending non-empty subset of `0..n`
+    pub(crate) fn dice_subset(n: usize) -> impl Strategy<Value = Vec<usize>> {
+        prop::sample::subsequence((0..n).collect::<Vec<_>>(), 1..=n)
+    }
+
+    proptest! {
+        #[test]
+        fn prop_subset_word(((sizes, w), subset) in sizes_and_word(5, 5).prop_flat_map(|sw| {
+            let n = sw.0.len();
+            (Just(sw), dice_subset(n))
+        })) {
+            let sw = subset_word(&w, &subset);
+            prop_assert_eq!(sw.len(), subset.iter().map(|&i| sizes[i]).sum::<usize>());
+            prop_assert!(sw.iter().all(|&x| subset.contains(&(x as usize))));
+            // A subsequence of `w`
+            let mut rest = w.iter();
+            prop_assert!(sw.iter().all(|x| rest.any(|y| y == x)));
+            prop_assert_eq!(subset_word(&sw, &subset), sw.clone());
+            prop_assert_eq!(subset_word(&w, &(0..sizes.len()).collect::<Vec<_>>()), w);
+        }
+    }
+}
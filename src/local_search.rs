@@ -0,0 +1,256 @@
+use itertools::Itertools;
+use log::{debug, info};
+
+use crate::{count_permutation_occurences, is_word_permutation_fair_up_to, subset_fairness, DiceTuple, Word, FDTS};
+
+/// Options for [`FDTS::new_local_search`]
+#[derive(Debug, Clone)]
+pub struct LocalSearchOptions {
+    /// Seed of the random generator, the search is deterministic given the seed
+    pub seed: u64,
+    /// Number of random starting words
+    pub restarts: usize,
+    /// Swap moves tried without decreasing the unfairness before restarting
+    pub patience: usize,
+    /// Stop after finding this many distinct (canonical) fair dice tuples
+    pub max_results: usize,
+}
+
+impl Default for LocalSearchOptions {
+    fn default() -> Self {
+        LocalSearchOptions {
+            seed: 0,
+            restarts: 1000,
+            patience: 300,
+            max_results: 1,
+        }
+    }
+}
+
+/// SplitMix64, small and seedable, so that seeds stay reproducible independently of any crate versions
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n` (up to a negligible bias)
+    fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+
+    fn shuffle(&mut self, word: &mut [u8]) {
+        for i in (1..word.len()).rev() {
+            word.swap(i, self.below(i + 1));
+        }
+    }
+}
+
+/// One `fair_up_to`-subset of the dice with its current contribution to the unfairness
+struct Subset {
+    dice: Vec<u8>,
+    permutations: Vec<Vec<u8>>,
+    /// Product of the dice sizes, i.e. the sum of the counts of all the permutations
+    total: i128,
+    value: u128,
+}
+
+/// Unfairness of a word: for every `fair_up_to`-subset of the dice, the sum of squared deviations
+/// of the counts of its permutations from their mean (scaled by the number of permutations
+/// to stay integral). It is zero exactly for the fair words.
+struct Unfairness {
+    subsets: Vec<Subset>,
+    value: u128,
+}
+
+impl Unfairness {
+    fn new(sizes: &[usize], fair_up_to: usize) -> Self {
+        let subsets = (0..sizes.len() as u8)
+            .combinations(fair_up_to)
+            .map(|dice| Subset {
+                permutations: dice.iter().cloned().permutations(fair_up_to).collect(),
+                total: dice.iter().map(|&d| sizes[d as usize] as i128).product(),
+                dice,
+                value: 0,
+            })
+            .collect();
+        Unfairness { subsets, value: 0 }
+    }
+
+    fn subset_value(s: &Subset, word: &[u8]) -> u128 {
+        let scale = s.permutations.len() as i128;
+        s.permutations
+            .iter()
+            .map(|p| {
+                let d = scale * count_permutation_occurences(p, word) as i128 - s.total;
+                (d * d) as u128
+            })
+            .sum()
+    }
+
+    fn evaluate(&mut self, word: &[u8]) -> u128 {
+        for s in &mut self.subsets {
+            s.value = Self::subset_value(s, word);
+        }
+        self.value = self.subsets.iter().map(|s| s.value).sum();
+        self.value
+    }
+
+    /// Update after swapping two occurrences of the letters `a` and `b` in `word`, returning the
+    /// previous values for `restore`. Swapping adjacent letters only changes their relative order,
+    /// so only the subsets with both of them are affected; otherwise all the subsets with either are.
+    fn update(&mut self, word: &[u8], a: u8, b: u8, adjacent: bool) -> Vec<(usize, u128)> {
+        let mut old = vec![];
+        for (i, s) in self.subsets.iter_mut().enumerate() {
+            let (has_a, has_b) = (s.dice.contains(&a), s.dice.contains(&b));
+            if (has_a && has_b) || (!adjacent && (has_a || has_b)) {
+                old.push((i, s.value));
+                let v = Self::subset_value(s, word);
+                self.value = self.value - s.value + v;
+                s.value = v;
+            }
+        }
+        old
+    }
+
+    fn restore(&mut self, old: Vec<(usize, u128)>) {
+        for (i, v) in old {
+            self.value = self.value - self.subsets[i].value + v;
+            self.subsets[i].value = v;
+        }
+    }
+}
+
+impl FDTS {
+    /// Look for fair dice tuples by local search: starting from random words with the right letter counts,
+    /// swap letters as long as the unfairness does not increase, restarting when it has not decreased
+    /// for `opts.patience` swaps.
+    /// Meant for sizes beyond the reach of combine; may find nothing even if fair dice exist.
+    ///
+    /// The found words are verified to be fair and stored in their canonical form (see `canonical_word`).
+    /// The result is not `complete`.
+    pub fn new_local_search(sizes: &[usize], fair_up_to: usize, opts: &LocalSearchOptions) -> Self {
+        let mut f = FDTS::new_empty(sizes);
+        f.criterion = subset_fairness(fair_up_to);
+        f.complete = false;
+        assert!(fair_up_to >= 1 && fair_up_to <= f.n());
+        let values: Word = (0..f.n() as u8).collect();
+        let mut rng = SplitMix64(opts.seed);
+        let mut unfairness = Unfairness::new(sizes, fair_up_to);
+        let mut word: Word = sizes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &s)| std::iter::repeat_n(i as u8, s))
+            .collect();
+        if word.len() < 2 {
+            return f;
+        }
+
+        for restart in 0..opts.restarts {
+            if f.dice.len() >= opts.max_results {
+                break;
+            }
+            rng.shuffle(&mut word);
+            let mut value = unfairness.evaluate(&word);
+            let mut since_improvement = 0;
+            while value > 0 && since_improvement < opts.patience {
+                since_improvement += 1;
+                // Half of the moves are adjacent swaps, which are cheaper and allow fine-tuning
+                let adjacent = rng.below(2) == 0;
+                let i = rng.below(word.len() - 1);
+                let j = if adjacent { i + 1 } else { rng.below(word.len()) };
+                if word[i] == word[j] {
+                    continue;
+                }
+                let (a, b) = (word[i], word[j]);
+                word.swap(i, j);
+                let old = unfairness.update(&word, a, b, adjacent);
+                if unfairness.value < value {
+                    since_improvement = 0;
+                }
+                if unfairness.value <= value {
+                    value = unfairness.value;
+                } else {
+                    word.swap(i, j);
+                    unfairness.restore(old);
+                }
+            }
+            if value == 0 {
+                assert!(is_word_permutation_fair_up_to(&word, &values, fair_up_to));
+                let canonical = f.canonical_word(&word);
+                if f.insert_dice_tuple(DiceTuple::from_word(&f, &canonical)) {
+                    info!("# Restart {} found fair dice tuple {}", restart, f.dice.last().unwrap().as_string());
+                }
+            } else {
+                debug!(" .. restart {} ended with unfairness {}", restart, value);
+            }
+        }
+        f
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::local_search::{LocalSearchOptions, SplitMix64, Unfairness};
+    use crate::{is_word_permutation_fair_up_to, DiceTuple, FDTS};
+
+    #[test]
+    fn test_unfairness() {
+        let f = FDTS::new_empty(&[2, 2]);
+        let w = |s: &str| DiceTuple::from_string(&f, s).word;
+        let mut u = Unfairness::new(&[2, 2], 2);
+        assert_eq!(u.evaluate(&w("ABBA")), 0);
+        // AB occurs 4 times and BA never: (2 * 4 - 4)^2 + (2 * 0 - 4)^2
+        assert_eq!(u.evaluate(&w("AABB")), 32);
+
+        // Incremental updates agree with a full evaluation and can be undone
+        let mut word = w("AABB");
+        word.swap(1, 2);
+        let old = u.update(&word, 0, 1, true);
+        assert_eq!(u.value, Unfairness::new(&[2, 2], 2).evaluate(&word));
+        u.restore(old);
+        assert_eq!(u.value, 32);
+    }
+
+    #[test]
+    fn test_shuffle() {
+        let mut rng = SplitMix64(1);
+        let mut w = vec![0, 0, 1, 1, 2, 2];
+        rng.shuffle(&mut w);
+        assert_eq!(w.iter().filter(|&&x| x == 2).count(), 2);
+        assert!((0..1000).all(|_| rng.below(7) < 7));
+    }
+
+    #[test]
+    fn test_local_search() {
+        let opts = LocalSearchOptions {
+            seed: 7,
+            max_results: 2,
+            ..Default::default()
+        };
+        let f = FDTS::new_local_search(&[6, 6, 6], 3, &opts);
+        assert_eq!(f.dice.len(), 2);
+        assert!(!f.complete);
+        assert!(f.dice.iter().all(|d| is_word_permutation_fair_up_to(&d.word, [0, 1, 2], 3)));
+        assert!(f.dice.iter().all(|d| f.canonical_word(&d.word) == d.word));
+        // Deterministic given the seed
+        assert_eq!(FDTS::new_local_search(&[6, 6, 6], 3, &opts), f);
+
+        // No [4, 4, 4] dice are fair, as 4^3 is not divisible by 3!
+        let none = FDTS::new_local_search(
+            &[4, 4, 4],
+            3,
+            &LocalSearchOptions {
+                restarts: 3,
+                patience: 100,
+                ..Default::default()
+            },
+        );
+        assert!(none.dice.is_empty());
+    }
+}
@@ -1,94 +1,29 @@
-use std::{
-    error::Error,
-    fs::File,
-    path::{Path, PathBuf},
-};
-
-use fairdice::{is_sorted, FDTS};
-use itertools::Itertools;
-use log::info;
+use std::{collections::HashMap, error::Error, fs::File, path::PathBuf, time::{Duration, Instant}};
+
+use fairdice::cache::{check_cache, count, find_one, load_or_compute, merge_files, save_search_results};
+use fairdice::progress::ProgressKind;
+use fairdice::{is_sorted, subset_fairness, CombineOptions, Criterion, LocalSearchOptions, Parallelism, Shard, WeightedFairness, FDTS};
 use log::LevelFilter;
+use log::{error, info};
 use structopt::StructOpt;
 
-fn load_or_compute(sizes: &[usize], up_to: usize, dir: &PathBuf) -> Result<FDTS, Box<dyn Error>> {
-    assert!(!sizes.is_empty());
-    assert!(up_to <= sizes.len());
-    if sizes.len() == 1 {
-        assert_eq!(up_to, 1);
-        return Ok(FDTS::new_single(sizes[0]));
-    }
-    let pf: PathBuf = format!("fdts_{}_fair{}.json.zstd", sizes.iter().format("_"), up_to).into();
-    let ps: PathBuf = [dir, &pf].iter().collect();
-    if Path::new(&ps).exists() {
-        let r = File::open(&ps)?;
-        let mut r = zstd::Decoder::new(r)?;
-        let f = FDTS::from_json(&mut r)?;
-        assert_eq!(up_to, f.fair_up_to);
-        assert_eq!(sizes, f.sizes);
-        info!(
-            "# Read FDTS {} (fair up to {}, {} dice tuples) from {:?}",
-            f.sizes_string(),
-            f.fair_up_to,
-            f.dice.len(),
-            &ps
-        );
-        return Ok(f);
-    }
-
-    let n = sizes.len();
-    let up_to_2 = std::cmp::min(up_to, n - 1);
-
-    fn sizes_and_mapped_positions(sizes: &[usize], position: usize) -> (Vec<usize>, Vec<isize>) {
-        let mut a_sizes: Vec<usize> = sizes.into();
-        a_sizes.remove(position);
-        let mut a_positions: Vec<isize> = (0..(sizes.len() - 1) as isize).collect();
-        a_positions.insert(position, -1);
-        (a_sizes, a_positions)
-    }
-
-    info!(
-        "# Gathering data for FDTS [{}] (fair up to {}) ...",
-        sizes.iter().format(","),
-        up_to
-    );
-    let (a_s, a_p) = sizes_and_mapped_positions(sizes, n - 2);
-    let da = load_or_compute(&a_s, up_to_2, dir)?;
-    let (b_s, b_p) = sizes_and_mapped_positions(sizes, n - 1);
-    let db = load_or_compute(&b_s, up_to_2, dir)?;
-
-    let mut checking = vec![];
-    for i in 0..(n - 2) {
-        let (c_s, c_p) = sizes_and_mapped_positions(sizes, i);
-        let dc = load_or_compute(&c_s, up_to_2, dir)?;
-        checking.push((dc, c_p));
-    }
-
-    let f = FDTS::new_combined(
-        da.mapped_as(&a_p),
-        db.mapped_as(&b_p),
-        checking.iter().map(|(c, p)| c.mapped_as(p)).collect_vec().as_slice(),
-        up_to,
-    );
-    let w = File::create(&ps)?;
-    let mut w = zstd::Encoder::new(w, 9)?.auto_finish();
-    f.write_json(&mut w)?;
-    info!(
-        "# Saved FDTS [{}] (fair up to {}, {} dice tuples) to {:?}",
-        f.sizes_string(),
-        f.fair_up_to,
-        f.dice.len(),
-        ps
-    );
-    Ok(f)
-}
-
 #[derive(StructOpt, Debug)]
 #[structopt(name = "permutation-fair-dice")]
 struct Opt {
-    /// Limit permutation fairness to all k-tuples (default: all)
+    /// Limit permutation fairness to all k-subsets of the dice (default: all)
     #[structopt(short, long, default_value = "-1")]
     fair_up_to: isize,
 
+    /// Fairness criterion by its tag: `fair{k}` (every k-subset of the dice is fair), `top{k}` (the first k places
+    /// are fair) or `gofirst` (same as `top1`, default: `fair{k}` for `--fair-up-to k`)
+    #[structopt(long, conflicts_with = "fair-up-to")]
+    criterion: Option<Criterion>,
+
+    /// Target weights of the permutations as a JSON file like `{"ABC": 2, "ACB": 1, ...}`, listing the dice
+    /// from the lowest to the highest number (missing permutations have weight 0)
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["fair-up-to", "criterion"])]
+    weights: Option<PathBuf>,
+
     /// Verbose mode
     #[structopt(short, long, parse(from_occurrences))]
     verbose: u8,
@@ -97,23 +32,188 @@ struct Opt {
     #[structopt(short, long, parse(from_os_str), default_value = "fdts_data")]
     output_dir: PathBuf,
 
+    /// Memory budget for the combine bins in MB; larger inputs are spilled to disk (default: unlimited)
+    #[structopt(long)]
+    memory_budget: Option<usize>,
+
+    /// Directory for the spill files (default: system temp dir)
+    #[structopt(long, parse(from_os_str))]
+    spill_dir: Option<PathBuf>,
+
+    /// Only compute shard i of N (0-based) of the top-level combine, writing a partial result file
+    #[structopt(long, value_name = "i/N")]
+    shard: Option<Shard>,
+
+    /// Number of threads for combining, 1 runs strictly single-threaded (default: all cores)
+    #[structopt(short, long)]
+    threads: Option<usize>,
+
+    /// Stop after this many seconds in total, including all the subproblems; the incomplete result is not cached
+    /// (default: no limit)
+    #[structopt(long)]
+    time_budget: Option<u64>,
+
+    /// Only look for one fair dice tuple (or show there is none), computing the subproblems only as far as needed
+    #[structopt(long)]
+    first: bool,
+
+    /// Only count the fair dice tuples without storing them (the subproblems are still cached)
+    #[structopt(long, conflicts_with = "first")]
+    count: bool,
+
+    /// Progress reporting: `terminal` bar, `json` lines on stderr, or `none`
+    #[structopt(long, default_value = "terminal")]
+    progress: ProgressKind,
+
     /// Sizes to process
     #[structopt(name = "SIZE")]
     sizes: Vec<usize>,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Merge partial FDTS files (e.g. all shards of a run) into the regular cache entry in the output dir
+    Merge {
+        /// Partial FDTS files to merge
+        #[structopt(name = "FILE", parse(from_os_str), required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Check that the projections of all cached FDTS in the output dir appear in their cached sub-problems
+    CheckCache,
+    /// Look for fair dice by (heuristic) local search, adding the found ones to a `.search.json.zstd` file
+    Search {
+        /// Seed of the random generator
+        #[structopt(long, default_value = "0")]
+        seed: u64,
+        /// Number of random starting words
+        #[structopt(long, default_value = "1000")]
+        restarts: usize,
+        /// Swaps without improvement before restarting
+        #[structopt(long, default_value = "300")]
+        patience: usize,
+        /// Stop after finding this many fair dice tuples
+        #[structopt(long, default_value = "1")]
+        results: usize,
+        /// Sizes to search for
+        #[structopt(name = "SIZE", required = true)]
+        sizes: Vec<usize>,
+    },
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let mut opt = Opt::from_args();
     if opt.fair_up_to < 0 {
-        opt.fair_up_to = opt.sizes.len() as isize;
+        opt.fair_up_to = match &opt.command {
+            Some(Command::Search { sizes, .. }) => sizes.len(),
+            _ => opt.sizes.len(),
+        } as isize;
+    }
+    if let Some(Command::Search { sizes, .. }) = &opt.command {
+        if opt.criterion.is_some() || opt.weights.is_some() {
+            return Err("search only supports fairness up to k (--fair-up-to), not --criterion or --weights".into());
+        }
+        if opt.fair_up_to == 0 {
+            return Err("search needs fairness up to at least 1 (every word is fair up to 0)".into());
+        }
+        subset_fairness(opt.fair_up_to as usize).check_dice(sizes.len())?;
     }
     simple_logging::log_to_stderr(if opt.verbose == 0 { LevelFilter::Info } else { LevelFilter::Debug });
     if !opt.output_dir.exists() {
         info!("Creating new dir {:?}", &opt.output_dir);
         std::fs::create_dir_all(&opt.output_dir)?;
     }
+    match &opt.command {
+        Some(Command::Merge { files }) => {
+            merge_files(files, &opt.output_dir)?;
+            return Ok(());
+        }
+        Some(Command::CheckCache) => {
+            let violations = check_cache(&opt.output_dir)?;
+            for v in &violations {
+                error!(
+                    "Projection {} of {} tuple(s) in {:?} (omitting dice {}) missing from {:?}",
+                    v.projected, v.count, v.file, v.omitted, v.sub_file
+                );
+            }
+            if !violations.is_empty() {
+                return Err(format!("{} cache consistency violations found", violations.len()).into());
+            }
+            info!("# Cache in {:?} is consistent", &opt.output_dir);
+            return Ok(());
+        }
+        Some(Command::Search {
+            seed,
+            restarts,
+            patience,
+            results,
+            sizes,
+        }) => {
+            assert!(is_sorted(sizes), "Sizes need to be non-descending in size");
+            let search_opts = LocalSearchOptions {
+                seed: *seed,
+                restarts: *restarts,
+                patience: *patience,
+                max_results: *results,
+            };
+            let f = FDTS::new_local_search(sizes, opt.fair_up_to as usize, &search_opts);
+            if f.dice.is_empty() {
+                info!("# No fair dice tuple found for sizes {:?}", sizes);
+            } else {
+                save_search_results(&f, &opt.output_dir)?;
+            }
+            return Ok(());
+        }
+        None => {}
+    }
     assert!(!opt.sizes.is_empty(), "Needs at least one SIZE");
     assert!(is_sorted(&opt.sizes), "Sizes need to be non-descending in size");
-    load_or_compute(&opt.sizes, opt.fair_up_to as usize, &opt.output_dir)?;
+    let combine_opts = CombineOptions {
+        memory_budget: opt.memory_budget.map(|mb| mb << 20),
+        spill_dir: opt.spill_dir.clone(),
+        shard: opt.shard,
+        parallelism: match opt.threads {
+            None | Some(0) => Parallelism::Global,
+            Some(1) => Parallelism::Sequential,
+            Some(t) => Parallelism::Threads(t),
+        },
+        progress: Some(opt.progress.reporter()),
+        cancel: None,
+        deadline: opt.time_budget.map(|t| Instant::now() + Duration::from_secs(t)),
+        limit: None,
+        count_only: false,
+    };
+    let criterion = match (&opt.weights, &opt.criterion) {
+        (Some(path), _) => {
+            let named: HashMap<String, u64> = serde_json::from_reader(File::open(path)?)?;
+            Criterion::new(WeightedFairness::from_named(opt.sizes.len(), &named)?)
+        }
+        (None, Some(c)) => c.clone(),
+        (None, None) => subset_fairness(opt.fair_up_to as usize),
+    };
+    criterion.check_dice(opt.sizes.len())?;
+    if opt.first {
+        let f = find_one(&opt.sizes, &criterion, &opt.output_dir, &combine_opts)?;
+        match f.dice.first() {
+            Some(d) => info!("# Found fair dice tuple {}", d.as_string()),
+            None if f.complete => info!("# No fair dice tuple exists for sizes {:?}", &opt.sizes),
+            None => return Err("stopped before finding a fair dice tuple".into()),
+        }
+        return Ok(());
+    }
+    if opt.count {
+        let stats = count(&opt.sizes, &criterion, &opt.output_dir, &combine_opts)?;
+        info!("# Counted {} fair dice tuples for sizes {:?}", stats.results, &opt.sizes);
+        if !stats.complete {
+            return Err("stopped early, the count is only a lower bound".into());
+        }
+        return Ok(());
+    }
+    let f = load_or_compute(&opt.sizes, &criterion, &opt.output_dir, &combine_opts)?;
+    if !f.complete {
+        return Err("time budget exceeded, the result is incomplete".into());
+    }
     Ok(())
 }
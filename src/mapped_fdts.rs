@@ -16,7 +16,7 @@ impl<'a> MappedFDTS<'a> {
             back[m] = Some(i);
         }
         assert!(is_sorted(map));
-        assert!(is_sorted(&back.iter().filter_map(|&x| x).collect_vec()));
+        assert!(is_sorted(back.iter().filter_map(|&x| x).collect_vec()));
         Self {
             fdts,
             map: map.into(),
@@ -69,8 +69,8 @@ impl<'a> MappedFDTS<'a> {
             return false;
         }
         for (&b1, &b2) in self.back.iter().zip(other.back.iter()) {
-            if b1.is_some() && b2.is_some() {
-                if self.fdts.sizes[b1.unwrap()] != other.fdts.sizes[b2.unwrap()] {
+            if let (Some(i1), Some(i2)) = (b1, b2) {
+                if self.fdts.sizes[i1] != other.fdts.sizes[i2] {
                     return false;
                 }
             }
@@ -81,7 +81,10 @@ impl<'a> MappedFDTS<'a> {
 
 #[cfg(test)]
 mod test {
-    use crate::{DiceTuple, Word};
+    use proptest::prelude::*;
+
+    use crate::test::{dice_subset, sizes_and_word};
+    use crate::{subset_word, DiceTuple, Word};
     use crate::{MappedFDTS, FDTS};
 
     #[test]
@@ -101,4 +104,25 @@ mod test {
         let mf2 = f.mapped_as(&[0, -1, 1, 2]);
         assert_eq!(mf2.iterate_words().collect::<Vec<_>>(), &[Word::from_slice(&[2, 3, 0, 3, 2, 0, 3])]);
     }
+
+    proptest! {
+        #[test]
+        fn prop_iterate_words_subset(((sizes, w), map, subset) in sizes_and_word(4, 4).prop_flat_map(|sw| {
+            let n = sw.0.len();
+            // Map the dice into a range of up to 6 indices, then pick a subset of the range
+            (Just(sw), prop::sample::subsequence((0..6).collect::<Vec<_>>(), n), dice_subset(6))
+        })) {
+            let mut f = FDTS::new_empty(&sizes);
+            f.insert_dice_tuple(DiceTuple::from_word(&f, &w));
+            let mf = MappedFDTS::new(&f, &map, 6);
+            let words = mf.iterate_words().collect::<Vec<_>>();
+            prop_assert_eq!(words.len(), 1);
+            prop_assert!(words[0].iter().zip(w.iter()).all(|(&m, &x)| m as usize == map[x as usize]));
+            prop_assert_eq!(mf.iterate_words_subset(&subset).collect::<Vec<_>>(), vec![subset_word(&words[0], &subset)]);
+            // Every prefix of the restriction to the mapped dice is a known prefix
+            for i in 0..=words[0].len() {
+                prop_assert!(mf.subset_word_in_prefixes(&words[0][..i]));
+            }
+        }
+    }
 }
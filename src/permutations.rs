@@ -15,7 +15,7 @@ where
     for i in 0..permutation.len() {
         inverse[permutation[i] as usize] = i;
     }
-    let mut counts = vec![0; permutation.len()];
+    let mut counts: SmallVec<[u64; 8]> = smallvec![0; permutation.len()];
     for &w in word.into_iter() {
         let wu = w as usize;
         if wu >= inverse.len() || inverse[wu] == usize::MAX {
@@ -70,36 +70,205 @@ where
     is_word_permutation_fair_up_to(word, values, values.len())
 }
 
+/// For every permutation of the dice `0..n` (in the order of `(0..n).permutations(n)`), its number of occurrences
+/// as a subsequence of `word`, i.e. the number of the ways to pick one number of every dice rolling in this order.
+pub fn count_permutations(word: &[u8], n: usize) -> Vec<u64> {
+    (0..n as u8)
+        .permutations(n)
+        .map(|p| count_permutation_occurences(&p, word))
+        .collect()
+}
+
+/// For every dice `0..n`, the number of the ways to pick one number of every dice (i.e. one letter occurrence
+/// of every dice in `word`) with this dice's number the highest (i.e. its occurrence last).
+pub fn count_go_first(word: &[u8], n: usize) -> Vec<u64> {
+    let mut seen = vec![0u64; n];
+    let mut counts = vec![0u64; n];
+    for &x in word {
+        let x = x as usize;
+        counts[x] += (0..n).filter(|&j| j != x).map(|j| seen[j]).product::<u64>();
+        seen[x] += 1;
+    }
+    counts
+}
+
+/// Check if every dice of `0..n` is equally likely to roll the highest number ("go-first" fairness)
+pub fn is_word_go_first_fair(word: &[u8], n: usize) -> bool {
+    count_go_first(word, n).iter().all_equal()
+}
+
+/// For every ordered `k`-tuple of distinct dice of `0..n` (in the order of `(0..n).permutations(k)`), the number of
+/// the ways to pick one number of every dice with these dice rolling the highest, second highest, ... `k`-th highest
+/// number (i.e. their occurrences last, in reverse order). For `k = 1` this is `count_go_first`.
+pub fn count_top_places(word: &[u8], n: usize, k: usize) -> Vec<u64> {
+    (0..n as u8)
+        .permutations(k)
+        .map(|top| {
+            // matched[m]: ways to pick the others and the places k..k-m+1, ending with the (k-m+1)-th place
+            let mut matched = vec![0u64; k + 1];
+            matched[0] = 1;
+            let mut seen = vec![0u64; n];
+            for &x in word {
+                if let Some(place) = top.iter().position(|&t| t == x) {
+                    let m = k - place;
+                    matched[m] += if m == 1 {
+                        (0..n as u8).filter(|j| !top.contains(j)).map(|j| seen[j as usize]).product::<u64>()
+                    } else {
+                        matched[m - 1]
+                    };
+                }
+                seen[x as usize] += 1;
+            }
+            // With no places, every pick counts
+            if k == 0 {
+                seen.iter().product()
+            } else {
+                matched[k]
+            }
+        })
+        .collect()
+}
+
+/// Check if the dice of `0..n` taking the first `k` places (the `k` highest numbers, in order) are equally likely to be
+/// any ordered `k`-tuple of the dice. This is go-first fairness for `k = 1` and permutation fairness for `k >= n - 1`.
+pub fn is_word_top_places_fair(word: &[u8], n: usize, k: usize) -> bool {
+    count_top_places(word, n, k).iter().all_equal()
+}
+
 #[cfg(test)]
 mod test {
-    use crate::permutations::{count_permutation_occurences, is_word_permutation_fair, is_word_permutation_fair_up_to};
+    use itertools::Itertools;
+    use proptest::prelude::*;
+
+    use crate::permutations::{
+        count_go_first, count_permutation_occurences, count_permutations, count_top_places, is_word_go_first_fair,
+        is_word_permutation_fair, is_word_permutation_fair_up_to, is_word_top_places_fair,
+    };
+    use crate::test::{dice_subset, fair_d6_d6_d6, sizes_and_word};
+    use crate::{subset_word, Word};
+
+    /// Count occurences of `permutation` by trying all subsequences of `word` of its length
+    fn naive_count(permutation: &[u8], word: &[u8]) -> u64 {
+        (0..word.len())
+            .combinations(permutation.len())
+            .filter(|idx| idx.iter().map(|&i| word[i]).eq(permutation.iter().cloned()))
+            .count() as u64
+    }
+
+    /// Relabel the dice of `word` to `subset`'s positions `0..subset.len()`
+    fn project(word: &[u8], subset: &[usize]) -> Word {
+        subset_word(&word.into(), subset)
+            .iter()
+            .map(|&x| subset.iter().position(|&i| i == x as usize).unwrap() as u8)
+            .collect()
+    }
 
     #[test]
     fn test_fairness() {
-        assert_eq!(is_word_permutation_fair(&[], &[42, 43]), true);
-        assert_eq!(is_word_permutation_fair(&[1, 1, 1, 1], &[2]), true);
-        assert_eq!(is_word_permutation_fair(&[1, 1, 1, 1], &[1]), true);
-        assert_eq!(is_word_permutation_fair(&[1, 1, 1, 0], &[0, 1]), false);
-        assert_eq!(is_word_permutation_fair(&[0, 1, 2, 2, 1, 0], &[0, 1, 2]), false);
-        assert_eq!(is_word_permutation_fair(&[3, 1, 3, 2, 2, 2, 1, 3, 3, 3, 3, 1, 2], &[1, 2, 3]), true);
-        assert_eq!(
-            is_word_permutation_fair(&[1, 3, 3, 2, 2, 2, 1, 3, 3, 3, 3, 1, 2], &[1, 2, 3]),
-            false
-        );
+        assert!(is_word_permutation_fair([], [42, 43]));
+        assert!(is_word_permutation_fair([1, 1, 1, 1], [2]));
+        assert!(is_word_permutation_fair([1, 1, 1, 1], [1]));
+        assert!(!is_word_permutation_fair([1, 1, 1, 0], [0, 1]));
+        assert!(!is_word_permutation_fair([0, 1, 2, 2, 1, 0], [0, 1, 2]));
+        assert!(is_word_permutation_fair([3, 1, 3, 2, 2, 2, 1, 3, 3, 3, 3, 1, 2], [1, 2, 3]));
+        assert!(!is_word_permutation_fair([1, 3, 3, 2, 2, 2, 1, 3, 3, 3, 3, 1, 2], [1, 2, 3]));
+
+        assert!(!is_word_permutation_fair_up_to([0, 1, 2, 2, 1, 0], [0, 1, 2], 3));
+        assert!(is_word_permutation_fair_up_to([0, 1, 2, 2, 1, 0], [0, 1, 2], 2));
+    }
+
+    #[test]
+    fn test_go_first() {
+        // In ABBA, A is highest with its second number, above both of B's
+        assert_eq!(count_go_first(&[0, 1, 1, 0], 2), vec![2, 2]);
+        assert_eq!(count_go_first(&[0, 1, 2, 2, 1, 0], 3), vec![4, 2, 2]);
+        assert!(is_word_go_first_fair(&[0, 1, 1, 0], 2));
+        assert!(!is_word_go_first_fair(&[0, 1, 2, 2, 1, 0], 3));
+        // Go-first fair but not permutation fair (BCA never happens)
+        let w = [1, 2, 2, 0, 1, 2];
+        assert_eq!(count_go_first(&w, 3), vec![2, 2, 2]);
+        assert!(is_word_go_first_fair(&w, 3));
+        assert!(!is_word_permutation_fair(w, [0, 1, 2]));
+    }
 
-        assert_eq!(is_word_permutation_fair_up_to(&[0, 1, 2, 2, 1, 0], &[0, 1, 2], 3), false);
-        assert_eq!(is_word_permutation_fair_up_to(&[0, 1, 2, 2, 1, 0], &[0, 1, 2], 2), true);
+    #[test]
+    fn test_top_places() {
+        let w = [0, 1, 2, 2, 1, 0];
+        assert_eq!(count_top_places(&w, 3, 0), vec![8]);
+        assert_eq!(count_top_places(&w, 3, 1), count_go_first(&w, 3));
+        // Orders ABC, ACB, BAC, BCA, CAB, CBA, read from the highest
+        assert_eq!(count_top_places(&w, 3, 2), vec![2, 2, 0, 2, 0, 2]);
+        assert_eq!(count_top_places(&w, 3, 2), count_top_places(&w, 3, 3));
+        assert_eq!(count_permutations(&w, 3), vec![2, 2, 0, 2, 0, 2]);
+        assert!(is_word_top_places_fair(&[0, 1, 1, 0], 2, 2));
+        // Go-first fair, but B is never second after C
+        let w = [1, 2, 2, 0, 1, 2];
+        assert!(is_word_top_places_fair(&w, 3, 1));
+        assert!(!is_word_top_places_fair(&w, 3, 2));
     }
 
     #[test]
     fn test_counting() {
-        assert_eq!(count_permutation_occurences(&[1], &[0, 2]), 0);
-        assert_eq!(count_permutation_occurences(&[1], &[1]), 1);
-        assert_eq!(count_permutation_occurences(&[0, 1], &[0, 1, 2, 0, 1]), 3);
-        assert_eq!(count_permutation_occurences(&[2], &vec![2; 42]), 42);
+        assert_eq!(count_permutation_occurences([1], &[0, 2]), 0);
+        assert_eq!(count_permutation_occurences([1], &[1]), 1);
+        assert_eq!(count_permutation_occurences([0, 1], &[0, 1, 2, 0, 1]), 3);
+        assert_eq!(count_permutation_occurences([2], &vec![2; 42]), 42);
         assert_eq!(
-            count_permutation_occurences(&[0, 3, 2, 1], &[0, 1, 2, 3, 0, 3, 4, 2, 1, 0, 0, 2, 1, 3]),
+            count_permutation_occurences([0, 3, 2, 1], &[0, 1, 2, 3, 0, 3, 4, 2, 1, 0, 0, 2, 1, 3]),
             9
         );
     }
+
+    proptest! {
+        #[test]
+        fn prop_counting((w, p) in sizes_and_word(5, 3).prop_flat_map(|(sizes, w)| {
+            let n = sizes.len();
+            (Just(w), dice_subset(n).prop_shuffle())
+        })) {
+            let p = p.iter().map(|&x| x as u8).collect_vec();
+            prop_assert_eq!(count_permutation_occurences(&p, &w), naive_count(&p, &w));
+        }
+
+        /// The first `n` places are the whole order, read from the highest number
+        #[test]
+        fn prop_top_places((sizes, w) in sizes_and_word(4, 3)) {
+            let n = sizes.len();
+            let all = count_top_places(&w, n, n);
+            for (top, c) in (0..n as u8).permutations(n).zip(&all) {
+                prop_assert_eq!(*c, count_permutation_occurences(top.iter().rev().copied().collect_vec(), &w));
+            }
+            for k in 0..=n {
+                prop_assert_eq!(count_top_places(&w, n, k).iter().sum::<u64>(), sizes.iter().map(|&s| s as u64).product::<u64>());
+            }
+        }
+
+        /// Fairness up to k is exactly full fairness of the projections onto all k-subsets
+        #[test]
+        fn prop_fairness_of_projections((sizes, u) in sizes_and_word(4, 3), mirror in any::<bool>()) {
+            // Any word followed by its reverse is fair up to 2
+            let w: Word = if mirror { u.iter().chain(u.iter().rev()).cloned().collect() } else { u };
+            let n = sizes.len();
+            let values = (0..n as u8).collect_vec();
+            if mirror {
+                prop_assert!(is_word_permutation_fair_up_to(&w, &values, std::cmp::min(2, n)));
+            }
+            for k in 1..=n {
+                let fair = is_word_permutation_fair_up_to(&w, &values, k);
+                let fair_projections = (0..n).combinations(k).all(|s| is_word_permutation_fair(project(&w, &s), (0..k as u8).collect_vec()));
+                prop_assert_eq!(fair, fair_projections);
+            }
+        }
+
+        /// Projections of fair dice are fair
+        #[test]
+        fn prop_projection_keeps_fairness(i in 0..11usize, subset in dice_subset(3), relabel in Just(vec![0u8, 1, 2]).prop_shuffle()) {
+            let f = fair_d6_d6_d6();
+            // Relabeling equally-sized dice keeps fairness
+            let w = f.dice[i].word.iter().map(|&x| relabel[x as usize]).collect_vec();
+            prop_assert!(is_word_permutation_fair(&w, [0, 1, 2]));
+            let pw = project(&w, &subset);
+            prop_assert!(is_word_permutation_fair(&pw, (0..subset.len() as u8).collect_vec()));
+            prop_assert!(f.project(&subset).dice.iter().any(|d| d.word == project(&f.dice[i].word, &subset)));
+        }
+    }
 }
@@ -0,0 +1,227 @@
+use std::{
+    fmt::Debug,
+    io::Write,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
+
+/// Snapshot of the progress of one combine
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ProgressUpdate {
+    /// Dice pairs interleaved so far
+    pub pairs_done: u64,
+    /// Dice pairs to interleave, may still grow while spilled partitions are loaded
+    pub pairs_total: u64,
+    pub candidates: u64,
+    pub results: u64,
+    pub elapsed_secs: f64,
+}
+
+/// Receiver of the progress of [`FDTS::new_combined_with`](crate::FDTS::new_combined_with).
+///
+/// Updates come from the worker threads, already throttled to a few per second per thread.
+pub trait Progress: Send + Sync + Debug {
+    /// A combine producing FDTS of `sizes` is starting
+    fn start(&self, _sizes: &[usize]) {}
+    fn update(&self, _p: &ProgressUpdate) {}
+    /// The combine is done, `p` holds the final counts
+    fn finish(&self, _p: &ProgressUpdate) {}
+}
+
+/// Reports nothing
+#[derive(Debug, Default)]
+pub struct SilentProgress;
+
+impl Progress for SilentProgress {}
+
+/// Draws an indicatif progress bar on the terminal
+#[derive(Debug, Default)]
+pub struct TerminalProgress {
+    bar: Mutex<Option<ProgressBar>>,
+}
+
+impl TerminalProgress {
+    fn draw(&self, p: &ProgressUpdate) {
+        if let Some(bar) = self.bar.lock().unwrap().as_ref() {
+            bar.set_length(p.pairs_total);
+            bar.set_position(p.pairs_done);
+            bar.set_message(format!(
+                "{} results, {} candidates, {:.2} cands/s",
+                p.results,
+                p.candidates,
+                p.candidates as f64 / p.elapsed_secs
+            ));
+        }
+    }
+}
+
+impl Progress for TerminalProgress {
+    fn start(&self, _sizes: &[usize]) {
+        let bar = ProgressBar::new(0);
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("combining: {percent}%|{wide_bar}| {pos}/{len} pairs [{elapsed}<{eta}] {msg}")
+                .progress_chars("##-"),
+        );
+        *self.bar.lock().unwrap() = Some(bar);
+    }
+
+    fn update(&self, p: &ProgressUpdate) {
+        self.draw(p);
+    }
+
+    fn finish(&self, p: &ProgressUpdate) {
+        self.draw(p);
+        if let Some(bar) = self.bar.lock().unwrap().take() {
+            bar.finish();
+        }
+    }
+}
+
+/// Writes one JSON object per line to stderr, at most once per `interval` (and once when done),
+/// e.g. `{"event":"progress","sizes":[6,6,6],"pairs_done":10,...}`
+#[derive(Debug)]
+pub struct JsonLinesProgress {
+    interval: Duration,
+    /// Sizes of the current combine and the time of the last written line
+    state: Mutex<(Vec<usize>, Option<Instant>)>,
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    event: &'a str,
+    sizes: &'a [usize],
+    #[serde(flatten)]
+    progress: Option<&'a ProgressUpdate>,
+}
+
+impl JsonLinesProgress {
+    pub fn new(interval: Duration) -> Self {
+        JsonLinesProgress {
+            interval,
+            state: Mutex::new((vec![], None)),
+        }
+    }
+
+    fn write_line(sizes: &[usize], event: &str, progress: Option<&ProgressUpdate>) {
+        let line = serde_json::to_string(&JsonLine { event, sizes, progress }).expect("progress is serializable");
+        // Progress is best-effort, a closed stderr must not abort the computation
+        let _ = writeln!(std::io::stderr().lock(), "{}", line);
+    }
+}
+
+impl Progress for JsonLinesProgress {
+    fn start(&self, sizes: &[usize]) {
+        let mut state = self.state.lock().unwrap();
+        *state = (sizes.to_vec(), Some(Instant::now()));
+        Self::write_line(sizes, "start", None);
+    }
+
+    fn update(&self, p: &ProgressUpdate) {
+        let mut state = self.state.lock().unwrap();
+        if state.1.is_some_and(|last| last.elapsed() < self.interval) {
+            return;
+        }
+        state.1 = Some(Instant::now());
+        Self::write_line(&state.0, "progress", Some(p));
+    }
+
+    fn finish(&self, p: &ProgressUpdate) {
+        let state = self.state.lock().unwrap();
+        Self::write_line(&state.0, "finish", Some(p));
+    }
+}
+
+/// Progress reporter selectable on the command line: `terminal`, `json` or `none`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressKind {
+    Terminal,
+    JsonLines,
+    Silent,
+}
+
+impl ProgressKind {
+    /// A new reporter of this kind, `JsonLines` writing at most one line per second
+    pub fn reporter(self) -> Arc<dyn Progress> {
+        match self {
+            ProgressKind::Terminal => Arc::<TerminalProgress>::default(),
+            ProgressKind::JsonLines => Arc::new(JsonLinesProgress::new(Duration::from_secs(1))),
+            ProgressKind::Silent => Arc::new(SilentProgress),
+        }
+    }
+}
+
+impl FromStr for ProgressKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "terminal" => Ok(ProgressKind::Terminal),
+            "json" => Ok(ProgressKind::JsonLines),
+            "none" => Ok(ProgressKind::Silent),
+            _ => Err(format!("unknown progress kind {:?}, expected terminal, json or none", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::progress::{JsonLine, JsonLinesProgress, Progress, ProgressKind, ProgressUpdate};
+
+    #[test]
+    fn test_json_line() {
+        let p = ProgressUpdate {
+            pairs_done: 3,
+            pairs_total: 10,
+            candidates: 7,
+            results: 1,
+            elapsed_secs: 0.5,
+        };
+        let line = serde_json::to_string(&JsonLine {
+            event: "progress",
+            sizes: &[6, 6],
+            progress: Some(&p),
+        })
+        .unwrap();
+        assert_eq!(
+            line,
+            r#"{"event":"progress","sizes":[6,6],"pairs_done":3,"pairs_total":10,"candidates":7,"results":1,"elapsed_secs":0.5}"#
+        );
+        let line = serde_json::to_string(&JsonLine {
+            event: "start",
+            sizes: &[6, 6],
+            progress: None,
+        })
+        .unwrap();
+        assert_eq!(line, r#"{"event":"start","sizes":[6,6]}"#);
+    }
+
+    #[test]
+    fn test_json_throttle() {
+        let j = JsonLinesProgress::new(Duration::from_secs(3600));
+        j.start(&[6, 6]);
+        let last = j.state.lock().unwrap().1;
+        j.update(&ProgressUpdate {
+            pairs_done: 0,
+            pairs_total: 0,
+            candidates: 0,
+            results: 0,
+            elapsed_secs: 0.0,
+        });
+        // Within the interval, no line is written and the timestamp is kept
+        assert_eq!(j.state.lock().unwrap().1, last);
+    }
+
+    #[test]
+    fn test_parse_kind() {
+        assert_eq!("json".parse(), Ok(ProgressKind::JsonLines));
+        assert_eq!("none".parse(), Ok(ProgressKind::Silent));
+        assert!("bar".parse::<ProgressKind>().is_err());
+    }
+}
@@ -0,0 +1,70 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::stable_hash;
+
+/// A deterministic slice of the work units of a combine, `index` out of `count` (0-based)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Shard {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Shard {
+    pub fn new(index: usize, count: usize) -> Self {
+        assert!(index < count, "shard index must be below the shard count");
+        Self { index, count }
+    }
+
+    /// Whether the work unit starting with word `w1` of the first input belongs to this shard.
+    /// Depends only on the word (by `stable_hash`), so the assignment is independent of binning and thread count,
+    /// and of the platform and build of the binary.
+    pub fn owns(&self, w1: &[u8]) -> bool {
+        (stable_hash(w1) % self.count as u64) as usize == self.index
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    /// Parse `i/N` with `0 <= i < N`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (i, n) = s.split_once('/').ok_or_else(|| format!("expected shard as i/N, got {:?}", s))?;
+        let index: usize = i.trim().parse().map_err(|e| format!("invalid shard index {:?}: {}", i, e))?;
+        let count: usize = n.trim().parse().map_err(|e| format!("invalid shard count {:?}: {}", n, e))?;
+        if index >= count {
+            return Err(format!(
+                "shard index {} out of range for {} shards (indices are 0-based)",
+                index, count
+            ));
+        }
+        Ok(Self { index, count })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Shard;
+
+    #[test]
+    fn test_shard() {
+        assert_eq!("2/5".parse::<Shard>(), Ok(Shard::new(2, 5)));
+        assert!("5/5".parse::<Shard>().is_err());
+        assert!("1".parse::<Shard>().is_err());
+        assert_eq!(Shard::new(0, 3).to_string(), "0/3");
+        let words: Vec<Vec<u8>> = (0..100u8).map(|i| vec![i % 3, i, i / 7]).collect();
+        for w in &words {
+            assert_eq!((0..3).filter(|&i| Shard::new(i, 3).owns(w)).count(), 1);
+        }
+        // The assignment is fixed, so shards of different builds fit together
+        assert!(Shard::new(2, 3).owns(&[1, 2, 0]));
+        assert!(Shard::new(4, 7).owns(&[2, 2, 1]));
+    }
+}
@@ -0,0 +1,37 @@
+//! Helpers shared by the integration tests and the benchmarks (the unit tests have their own `TempDir`)
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A fresh scratch directory, removed with all its contents on drop (also when a test fails)
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Directory `fairdice-{name}-{pid}` in the system temp dir, `name` needs to be unique among concurrent tests
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("fairdice-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
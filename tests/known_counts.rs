@@ -0,0 +1,82 @@
+//! Regression suite of known numbers of fair dice tuples (up to relabeling of equally-sized dice).
+//!
+//! The expensive cases are `#[ignore]`d, run them with `cargo test --release -- --ignored`.
+
+use fairdice::cache::load_or_compute;
+use fairdice::{subset_fairness, CombineOptions};
+use itertools::Itertools;
+
+mod common;
+
+use common::TempDir;
+
+/// Compute the FDTS from scratch in a fresh cache dir and check the number of dice tuples
+fn check_count(sizes: &[usize], fair_up_to: usize, count: usize) {
+    let dir = TempDir::new(&format!("known-counts-{}-fair{}", sizes.iter().format("_"), fair_up_to));
+    let f = load_or_compute(sizes, &subset_fairness(fair_up_to), &dir, &CombineOptions::default()).unwrap();
+    assert_eq!(f.dice.len(), count, "FDTS {:?} fair up to {}", sizes, fair_up_to);
+}
+
+#[test]
+fn d6_d6() {
+    check_count(&[6, 6], 2, 29);
+}
+
+#[test]
+fn d6_d12() {
+    check_count(&[6, 12], 2, 676);
+}
+
+#[test]
+fn d6_d6_d6() {
+    check_count(&[6, 6, 6], 3, 11);
+}
+
+#[test]
+fn d6_d6_d6_fair2() {
+    check_count(&[6, 6, 6], 2, 2421);
+}
+
+#[test]
+fn d4_d4_d4() {
+    // 4^3 is not divisible by 3!
+    check_count(&[4, 4, 4], 3, 0);
+}
+
+#[test]
+fn d6_d6_d12() {
+    check_count(&[6, 6, 12], 3, 740);
+}
+
+#[test]
+fn d2_d2_d4_d4_fair2() {
+    check_count(&[2, 2, 4, 4], 2, 51);
+}
+
+#[test]
+fn d4_d4_d4_d4_fair2() {
+    check_count(&[4, 4, 4, 4], 2, 218);
+}
+
+#[test]
+fn d4_d6_d6_d6_fair3() {
+    check_count(&[4, 6, 6, 6], 3, 16);
+}
+
+#[test]
+#[ignore]
+fn d12_d12() {
+    check_count(&[12, 12], 2, 30554);
+}
+
+#[test]
+#[ignore]
+fn d6_d12_d12() {
+    check_count(&[6, 12, 12], 3, 44902);
+}
+
+#[test]
+#[ignore]
+fn d6_d6_d12_d12() {
+    check_count(&[6, 6, 12, 12], 4, 12);
+}